@@ -0,0 +1,64 @@
+//! An append-only local log of sent notifications, for an audit trail that
+//! doesn't rely on Pushover's own records.
+
+use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher, SipHasher};
+use std::io::prelude::*;
+use std::path;
+use rustc_serialize::json;
+use time;
+use Parameters;
+
+/// One sent notification, as recorded in the audit log. The body is only
+/// recorded as a hash, so the log doesn't keep the messages themselves.
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq, Clone)]
+pub struct Entry {
+    /// When the notification was sent, as a Unix timestamp
+    pub time: i64,
+    pub title: Option<String>,
+    pub priority: Option<i8>,
+    /// The device or devices the notification was sent to, comma-separated
+    pub device: Option<String>,
+    /// The ID Pushover gave the request
+    pub request_id: String,
+    /// A hash of the message body, in hex
+    pub body_hash: String
+}
+
+impl Entry {
+    /// Creates an entry for a message sent now with the given parameters.
+    pub fn new(message: &str, parameters: &[Parameters], request_id: &str) -> Entry {
+        let mut hasher = SipHasher::new();
+        message.hash(&mut hasher);
+        let mut entry = Entry {
+            time: time::get_time().sec,
+            title: None,
+            priority: None,
+            device: None,
+            request_id: request_id.to_string(),
+            body_hash: format!("{:016x}", hasher.finish())
+        };
+
+        for p in parameters.iter() {
+            match *p {
+                Parameters::Title(ref t) => entry.title = Some(t.clone()),
+                Parameters::Priority(priority) => entry.priority = Some(priority),
+                Parameters::Device(ref d) => entry.device = Some(d.clone()),
+                Parameters::Devices(ref ds) => entry.device = Some(ds.join(",")),
+                _ => {}
+            }
+        }
+        entry
+    }
+}
+
+/// Appends an entry to the audit log at the given path as a line of JSON,
+/// creating the log if it doesn't exist.
+pub fn record(path: &path::Path, entry: &Entry) -> Result<(), ()> {
+    let line = try!(json::encode(entry).map_err(|_| ()));
+
+    match OpenOptions::new().append(true).create(true).open(path) {
+        Ok(mut f) => writeln!(f, "{}", line).map_err(|_| ()),
+        Err(_) => Err(())
+    }
+}
@@ -1,89 +1,1054 @@
 extern crate po;
 extern crate rustc_serialize;
 extern crate docopt;
+extern crate log;
+extern crate dirs;
+extern crate directories;
+extern crate regex;
+extern crate time;
 
+use directories::ProjectDirs;
 use docopt::Docopt;
+use log::{LogLevelFilter, LogMetadata, LogRecord};
 use std::io::prelude::*;
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
 use po::Parameters;
+use regex::Regex;
+use rustc_serialize::base64::{self, ToBase64};
 
 static USAGE: &'static str = "
-Usage: po [options]
-       po [options] <message>
+Usage: po [options] [--exclude-device <name>]...
+       po [options] [--exclude-device <name>]... <message>
        po --setup <token> <user>
        po --setup
+       po --cancel-tag <tag>
+       po [options] --glance [<message>]
+       po [options] --gist-cleanup
+       po --check
+       po --check-format [<token> <user>]
+       po --help-priorities
+       po --help-sounds
+       po --completions <shell>
+       po [options] [--exclude-device <name>]... --wrap [--] <command>...
 
 Options:
     -h, --help                      Display this information.
+    --help-priorities               List what each priority means.
+    --help-sounds                   List the notification sounds available
+                                    for --sound.
+    --completions <shell>           Print a tab completion script for bash,
+                                    zsh, or fish.
     --setup                         Setup po with a given Pushover API token
                                     and user key. If neither are provided,
                                     then --setup prints setup instructions.
     -t <title>, --title <title>     The title to give the notification.
+    --hostname                      Prefix the title with this computer's
+                                    hostname, or use the hostname as the
+                                    title if none is given.
     -p <priority>                   A priority for the notification,
-                                    from -2 to 2 [default: 0].
+                                    from -2 to 2 (default: 0).
+    --silent-log                    Send at the lowest priority (-p -2): no
+                                    notification or sound, the message only
+                                    appears in the Pushover app.
     -d <device>, --device <device>  Specify which device should receive
                                     the notification.
+    --exclude-device <name>         Send to every active device except this
+                                    one; repeat it to exclude several.
     -s <sound>, --sound <sound>     Specify a notification sound from the API
                                     sound list.
+    --silent                        Send the notification without a sound;
+                                    overrides --sound.
+    --url <url>                     A supplementary URL to send with the
+                                    notification.
+    --url-title <title>             A title to give the supplementary URL.
+    --params-file <path>            Read the title, priority, sound, device,
+                                    url, and url_title from a JSON file;
+                                    flags take precedence.
+    --callback <url>                A URL for Pushover to call when an
+                                    emergency (-p 2) notification is
+                                    acknowledged.
     -g, --gist                      If the message is too long to send
-                                    (>1024 bytes), then upload it to GitHub
-                                    Gist and link it in the notification.
+                                    (see --max-length), then upload it to
+                                    GitHub Gist and link it in the
+                                    notification.
     --always-gist                   Always upload the message to GitHub Gist
                                     and link it in the notification.
-    --debug                         Print debugging information.
+    --github-token <token>          A GitHub personal access token to upload
+                                    Gists with, so that --gist-cleanup can
+                                    find them later.
+    --gist-cleanup                  Delete the Gists po uploaded more than
+                                    --gist-max-age days ago; needs a GitHub
+                                    token from --github-token or the config.
+    --gist-max-age <days>           With --gist-cleanup, how old a Gist must
+                                    be to be deleted [default: 30].
+    --gist-required                 Don't send the notification at all if
+                                    the --gist or --always-gist upload
+                                    fails; po exits with status 1.
+    --retries <n>                   How many times to retry a send or upload
+                                    that failed with a transient error, such
+                                    as a lost connection [default: 0].
+    --no-truncate                   Refuse to send messages that are too long
+                                    (see --max-length) instead of truncating
+                                    them, unless they are being uploaded.
+    --max-length <chars>            The longest message your Pushover plan
+                                    accepts (default: 1024).
+    --markdown                      Render **bold**, *italic*, and
+                                    [text](url) Markdown in the message.
+    --allow-empty                   Send an empty message as a single space
+                                    instead of refusing to send it.
+    --gist-ext <ext>                An extension such as .log or .rs to give
+                                    the Gist's file name, so GitHub
+                                    highlights it.
+    --paste-url <url>               Upload messages for --gist and
+                                    --always-gist to an anonymous paste
+                                    service such as https://0x0.st instead of
+                                    GitHub Gist.
+    --stdin-binary-safe             If standard input isn't text, upload it
+                                    base64-encoded (as with --gist) and
+                                    send a short summary, instead of
+                                    replacing the bytes that aren't UTF-8;
+                                    nothing is sent if the upload fails.
+    --parse-headers                 Read X-Priority and X-Title headers from
+                                    the start of standard input, ending at
+                                    the first blank line, and strip them from
+                                    the message. Flags take precedence.
+    --dedup-window <seconds>        Don't send a message identical to one
+                                    sent within the given number of seconds.
+    --head <lines>                  Only send the first <lines> lines of
+                                    standard input, plus any --tail, reading
+                                    it a line at a time; --gist and
+                                    --always-gist still upload all of it.
+    --tail <lines>                  Only send the last <lines> lines of
+                                    standard input, plus any --head.
+    --watch                         Keep reading standard input, sending a
+                                    notification for each line.
+    --grep <regex>                  With --watch, only send lines matching
+                                    the given regular expression.
+    --watch-interval <seconds>      With --watch, send at most one
+                                    notification per interval: the most
+                                    severe line matched during it, when it
+                                    ends, counting the others [default: 10].
+    --wrap                          Run the given command and send its output,
+                                    raising the priority to at least 1 and
+                                    noting the exit code in the title if it
+                                    fails.
+    --title-from-first-line         Use the first line of standard input as
+                                    the title (unless -t is given) and the
+                                    rest as the message.
+    --wait-ack                      Send the notification at emergency
+                                    priority and wait until it's
+                                    acknowledged, printing who acknowledged
+                                    it. Exits with status 3 if it isn't
+                                    acknowledged within --ack-timeout.
+    --poll-interval <seconds>       With --wait-ack, how often to check
+                                    whether the notification has been
+                                    acknowledged [default: 5].
+    --ack-timeout <seconds>         With --wait-ack, how long to wait for
+                                    acknowledgement [default: 3600].
+    --repeat <count>                Send the message <count> times, stopping
+                                    at the first failure [default: 1].
+    --interval <seconds>            With --repeat, how long to wait between
+                                    sends [default: 60].
+    --glance                        Update your Glances widgets instead of
+                                    sending a notification, setting their
+                                    title from --title and text from the
+                                    message, along with any of --subtext,
+                                    --count, and --percent.
+    --subtext <text>                With --glance, the widgets' second line
+                                    of text.
+    --count <n>                     With --glance, the number to show.
+    --percent <n>                   With --glance, a percentage from 0 to
+                                    100 to show as progress.
+    --cancel-tag <tag>              Cancel every outstanding emergency
+                                    notification sent with the given tag.
+    --check                         Check that the config is readable, the
+                                    Pushover API is reachable, and the stored
+                                    API token and user key are valid.
+    --check-format                  Check that the stored (or given) API
+                                    token and user key are well-formed,
+                                    without contacting Pushover.
+    --audit-log <path>              Append a line recording each notification
+                                    sent (but only a hash of its message)
+                                    to the given file.
+    --fail-on-suppress              Exit with status 4, rather than 0, when
+                                    a message isn't sent because it's a
+                                    duplicate (--dedup-window) or wasn't
+                                    confirmed (--confirm).
+    --preview                       Print the message as it would be sent,
+                                    after --markdown conversion and
+                                    truncation, without sending it.
+    --confirm                       Show the notification and ask for
+                                    confirmation on the terminal before
+                                    sending it.
+    -q, --quiet                     Don't print the URL of uploaded messages
+                                    to standard error.
+    --insecure                      Disable TLS certificate verification.
+                                    UNSAFE: only for controlled environments
+                                    behind a TLS-intercepting proxy.
+    --debug                         Print debugging information to standard
+                                    error.
 ";
 
-#[derive(RustcDecodable, Debug)]
+#[derive(RustcDecodable, Debug, Clone)]
 struct Args {
     arg_message: Option<String>,
     arg_token: String,
     arg_user: String,
-    flag_p: i8,
+    arg_command: Vec<String>,
+    flag_p: Option<i8>,
+    flag_silent_log: bool,
+    flag_help_priorities: bool,
+    flag_help_sounds: bool,
+    flag_completions: Option<String>,
     flag_title: Option<String>,
+    flag_hostname: bool,
     flag_device: Option<String>,
+    flag_exclude_device: Vec<String>,
     flag_sound: Option<String>,
+    flag_silent: bool,
+    flag_url: Option<String>,
+    flag_url_title: Option<String>,
+    flag_params_file: Option<String>,
+    flag_callback: Option<String>,
     flag_setup: bool,
     flag_gist: bool,
     flag_always_gist: bool,
+    flag_github_token: Option<String>,
+    flag_gist_cleanup: bool,
+    flag_gist_max_age: i64,
+    flag_gist_required: bool,
+    flag_retries: u32,
+    flag_no_truncate: bool,
+    flag_allow_empty: bool,
+    flag_markdown: bool,
+    flag_max_length: Option<usize>,
+    flag_gist_ext: Option<String>,
+    flag_paste_url: Option<String>,
+    flag_stdin_binary_safe: bool,
+    flag_parse_headers: bool,
+    flag_title_from_first_line: bool,
+    flag_dedup_window: Option<i64>,
+    flag_head: Option<usize>,
+    flag_tail: Option<usize>,
+    flag_watch: bool,
+    flag_grep: Option<String>,
+    flag_watch_interval: i64,
+    flag_wrap: bool,
+    flag_wait_ack: bool,
+    flag_poll_interval: u64,
+    flag_ack_timeout: u64,
+    flag_repeat: u32,
+    flag_interval: u64,
+    flag_glance: bool,
+    flag_subtext: Option<String>,
+    flag_count: Option<i64>,
+    flag_percent: Option<u8>,
+    flag_cancel_tag: Option<String>,
+    flag_check: bool,
+    flag_check_format: bool,
+    flag_confirm: bool,
+    flag_preview: bool,
+    flag_fail_on_suppress: bool,
+    flag_audit_log: Option<String>,
+    flag_quiet: bool,
+    flag_insecure: bool,
     flag_debug: bool
 }
 
+// Prints library log messages to standard error for --debug
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _: &LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &LogRecord) {
+        writeln!(&mut std::io::stderr(), "po: {}: {}",
+                 record.level(), record.args()).unwrap();
+    }
+}
+
 // Consume our arguments struct and produce a vector of Parameters for our
 // po send function
 fn parse_parameters(args: Args) -> Vec<Parameters> {
-    let mut parameters: Vec<Parameters> = Vec::new();
+    po::flags::Flags {
+        priority: args.flag_p,
+        title: args.flag_title,
+        device: args.flag_device,
+        sound: args.flag_sound,
+        silent: args.flag_silent,
+        url: args.flag_url,
+        url_title: args.flag_url_title,
+        callback: args.flag_callback,
+        always_gist: args.flag_always_gist,
+        debug: args.flag_debug
+    }.parameters()
+}
+
+// The headers --parse-headers recognises, in lowercase
+const HEADERS: &'static [&'static str] = &["x-priority", "x-title"];
+
+// Split an email-style header block off the front of a message: one or more
+// `Name: value` lines of recognised HEADERS, ended by the first blank line.
+// If the message doesn't start with a complete block of them, such as a
+// message starting "ERROR: disk full", there are no headers and the message
+// is returned untouched.
+fn split_headers(message: &str) -> (Vec<(String, String)>, String) {
+    let mut headers = Vec::new();
+    let mut start = 0;
+
+    while let Some(offset) = message[start..].find('\n') {
+        let end = start + offset;
+        let line = message[start..end].trim_right_matches('\r');
+
+        if line == "" {
+            if headers.is_empty() {
+                break;
+            }
+            return (headers, message[end + 1..].to_string());
+        }
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => break
+        };
+        let name = line[..colon].to_lowercase();
+        if !HEADERS.iter().any(|&h| h == name) {
+            break;
+        }
+        headers.push((name, line[colon + 1..].trim().to_string()));
+        start = end + 1;
+    }
+    (Vec::new(), message.to_string())
+}
+
+// Apply any X-Priority and X-Title headers to arguments that weren't given on
+// the command line.
+fn apply_headers(args: &mut Args, headers: Vec<(String, String)>) {
+    for (name, value) in headers.into_iter() {
+        match name.as_ref() {
+            "x-priority" => {
+                if let Ok(p) = value.parse::<i8>() {
+                    if args.flag_p.is_none() {
+                        args.flag_p = Some(p);
+                    }
+                }
+            },
+            "x-title" => {
+                if args.flag_title.is_none() {
+                    args.flag_title = Some(value);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+// Apply a --params-file profile to arguments that weren't given on the command
+// line.
+fn apply_profile(args: &mut Args, path: &str) {
+    let profile = match po::flags::Profile::read(Path::new(path)) {
+        Ok(profile) => profile,
+        Err(e) => {
+            println!("po: couldn't read params file {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    if args.flag_title.is_none() {
+        args.flag_title = profile.title;
+    }
+    if args.flag_p.is_none() {
+        args.flag_p = profile.priority;
+    }
+    if args.flag_sound.is_none() {
+        args.flag_sound = profile.sound;
+    }
+    if args.flag_device.is_none() {
+        args.flag_device = profile.device;
+    }
+    if args.flag_url.is_none() {
+        args.flag_url = profile.url;
+    }
+    if args.flag_url_title.is_none() {
+        args.flag_url_title = profile.url_title;
+    }
+}
+
+// Apply any --params-file, then the config's severity rules for `message` if
+// one is given, then defaults from the config file, to arguments that weren't
+// given on the command line.
+fn apply_config(args: &mut Args, config: &po::config::Config, message: Option<&str>) {
+    if let Some(path) = args.flag_params_file.take() {
+        apply_profile(args, path.as_ref());
+    }
+    if args.flag_p.is_none() {
+        if let Some(message) = message {
+            args.flag_p = config.severity(message);
+        }
+    }
+    if args.flag_p.is_none() {
+        args.flag_p = config.default_priority;
+    }
+    if args.flag_device.is_none() && args.flag_exclude_device.is_empty() {
+        args.flag_device = config.default_device.clone();
+    }
+    if args.flag_max_length.is_none() {
+        args.flag_max_length = config.max_message_len;
+    }
+    if args.flag_github_token.is_none() {
+        args.flag_github_token = config.github_token.clone();
+    }
+    if args.flag_audit_log.is_none() {
+        args.flag_audit_log = config.audit_log.as_ref()
+                                    .map(|p| p.to_string_lossy().into_owned());
+    }
+    if args.flag_title.is_none() {
+        if let Some(ref template) = config.title_template {
+            args.flag_title = Some(po::template::expand(template.as_ref(), &[]));
+        }
+    }
+    if args.flag_sound.is_none() && !args.flag_silent {
+        args.flag_sound = config.default_sound(args.flag_p.unwrap_or(0));
+    }
+}
+
+// Run the command given to --wrap, echoing and returning its combined output,
+// and adjust the title and priority of our arguments to its exit status
+fn wrap(args: &mut Args) -> String {
+    let program = args.arg_command[0].clone();
+    let output = match Command::new(&program).args(&args.arg_command[1..]).output() {
+        Ok(output) => output,
+        Err(e) => {
+            println!("po: Couldn't run {}: {}", program, e);
+            std::process::exit(1);
+        }
+    };
+    let mut message = String::from_utf8_lossy(&output.stdout).into_owned();
+    message.push_str(String::from_utf8_lossy(&output.stderr).as_ref());
+    print!("{}", message);
+
+    let name = args.flag_title.clone().unwrap_or(program);
+    if output.status.success() {
+        args.flag_title = Some(format!("{} succeeded", name));
+    }
+    else {
+        let code = match output.status.code() {
+            Some(code) => format!("exit {}", code),
+            None => "killed by signal".to_string()
+        };
+        args.flag_title = Some(format!("{} failed ({})", name, code));
+        if args.flag_p.unwrap_or(0) < 1 {
+            args.flag_p = Some(1);
+        }
+    }
+    message
+}
+
+// Read standard input a line at a time, echoing it, and return a summary of
+// its first `head` and last `tail` lines. Only the lines in the summary are
+// kept unless `keep_full` is set, in which case the whole input is returned
+// too.
+fn read_summary(head: usize, tail: usize, keep_full: bool) -> (String, Option<String>) {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let mut first = String::new();
+    let mut last: VecDeque<String> = VecDeque::with_capacity(tail);
+    let mut full = String::new();
+    let mut lines = 0;
+    let mut raw = Vec::new();
+
+    while input.read_until(b'\n', &mut raw).unwrap() > 0 {
+        let line = String::from_utf8_lossy(&raw).into_owned();
+        print!("{}", line);
+        if keep_full {
+            full.push_str(line.as_ref());
+        }
+        if lines < head {
+            first.push_str(line.as_ref());
+        }
+        else if tail > 0 {
+            if last.len() == tail {
+                last.pop_front();
+            }
+            last.push_back(line);
+        }
+        lines += 1;
+        raw.clear();
+    }
+
+    let omitted = lines - std::cmp::min(lines, head) - last.len();
+    let mut summary = first;
+    if omitted > 0 {
+        summary.push_str(format!("[... {} lines omitted ...]\n", omitted).as_ref());
+    }
+    for line in last.iter() {
+        summary.push_str(line.as_ref());
+    }
+    (summary, if keep_full { Some(full) } else { None })
+}
+
+// For --stdin-binary-safe, base64-encode standard input that isn't text,
+// returning a short summary to send and the encoded input, with a note on
+// how to decode it, to upload.
+fn binary_summary(raw: &[u8]) -> (String, String) {
+    let config = base64::Config {
+        char_set: base64::CharacterSet::Standard,
+        newline: base64::Newline::LF,
+        pad: true,
+        line_length: Some(76)
+    };
+    let summary = format!("Binary input ({} bytes), uploaded base64-encoded.", raw.len());
+    let full = format!("This input wasn't text, so it's base64-encoded below; decode it \
+                        with `base64 -d` to recover the original {} bytes.\n\n{}\n",
+                       raw.len(), raw.to_base64(config));
+    (summary, full)
+}
 
-    if args.flag_p != 0 {
-        parameters.push(Parameters::Priority(args.flag_p));
+// Bytes of standard input kept beyond four per character of the longest
+// message, as room for a --parse-headers block or --title-from-first-line
+const STDIN_SLACK: usize = 65536;
+
+// Read standard input, echoing it, and return up to `limit` bytes of it; the
+// rest is echoed but not kept, so arbitrarily large pipes don't have to fit
+// in memory. With no limit, all of it is returned.
+fn read_stdin(limit: Option<usize>) -> Vec<u8> {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut output = stdout.lock();
+    let mut kept = Vec::new();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let read = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                writeln!(&mut std::io::stderr(),
+                         "po: couldn't read standard input: {}", e).unwrap();
+                std::process::exit(1);
+            }
+        };
+        output.write_all(&buf[..read]).unwrap();
+        let keep = match limit {
+            Some(limit) => std::cmp::min(read, limit.saturating_sub(kept.len())),
+            None => read
+        };
+        kept.extend_from_slice(&buf[..keep]);
+    }
+    kept
+}
+
+// Read standard input a line at a time until it ends, echoing it and sending
+// each line (or each line matching --grep) as its own notification, at most
+// once per --watch-interval. Lines matched within an interval are held, and
+// the most severe of them is sent when it ends, or when input ends. Unless
+// `priority_given`, each line's priority is chosen by the config's severity
+// rules.
+fn watch(config: &po::config::Config, args: Args, priority_given: bool,
+         dedup_store: &Path) {
+    let filter = match args.flag_grep {
+        Some(ref pattern) => match Regex::new(pattern.as_ref()) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                println!("po: Invalid --grep regex: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None
+    };
+    // Read on another thread, so that a held line can be sent when its
+    // interval ends even while no input arrives:
+    let (lines, received) = mpsc::channel();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        let mut input = stdin.lock();
+        let mut raw = Vec::new();
+
+        while input.read_until(b'\n', &mut raw).unwrap() > 0 {
+            if lines.send(String::from_utf8_lossy(&raw).into_owned()).is_err() {
+                break;
+            }
+            raw.clear();
+        }
+    });
+    let mut last_sent: Option<i64> = None;
+    // The most severe line matched since the last send, with its priority,
+    // and how many lines were matched in all:
+    let mut held: Option<(String, Option<i8>)> = None;
+    let mut matched = 0;
+
+    loop {
+        let next = match (held.is_some(), last_sent) {
+            (true, Some(sent)) => {
+                let wait = sent + args.flag_watch_interval - time::get_time().sec;
+                received.recv_timeout(Duration::from_secs(std::cmp::max(wait, 0) as u64))
+            },
+            _ => received.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        let ended = match next {
+            Ok(line) => {
+                print!("{}", line);
+                let line = line.trim_right();
+                let wanted = line != "" && match filter {
+                    Some(ref re) => re.is_match(line),
+                    None => true
+                };
+                if wanted {
+                    let priority = if priority_given {
+                        None
+                    }
+                    else {
+                        config.severity(line)
+                    };
+                    let rank = |p: Option<i8>| p.or(args.flag_p).unwrap_or(0);
+                    let more_severe = match held {
+                        Some((_, held_priority)) => rank(priority) >= rank(held_priority),
+                        None => true
+                    };
+                    if more_severe {
+                        held = Some((line.to_string(), priority));
+                    }
+                    matched += 1;
+                }
+                false
+            },
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true
+        };
+
+        let now = time::get_time().sec;
+        let waiting = match last_sent {
+            Some(sent) => now - sent < args.flag_watch_interval,
+            None => false
+        };
+        if ended || !waiting {
+            if let Some((line, priority)) = held.take() {
+                let message = if matched > 1 {
+                    format!("{}\n[{} more lines skipped]", line, matched - 1)
+                }
+                else {
+                    line
+                };
+                let mut line_args = args.clone();
+                if priority.is_some() {
+                    line_args.flag_p = priority;
+                }
+                send(config.token.as_ref(), config.user.as_ref(), message.as_ref(), None,
+                     line_args, dedup_store);
+                last_sent = Some(now);
+                matched = 0;
+            }
+        }
+        if ended {
+            break;
+        }
+    }
+}
+
+// Split the first line off a message to use as its title. A single-line
+// message is used as both the title and the message, since Pushover won't
+// send an empty one.
+fn split_title(message: &str) -> (String, String) {
+    match message.find('\n') {
+        Some(newline) => {
+            let title = message[..newline].trim_right_matches('\r').to_string();
+            let body = message[newline + 1..].to_string();
+            if body.trim() == "" {
+                (title.clone(), title)
+            }
+            else {
+                (title, body)
+            }
+        },
+        None => (message.to_string(), message.to_string())
+    }
+}
+
+// Produce the request Options selected by our arguments
+fn parse_options(args: &Args) -> po::Options {
+    let mut options = po::Options::default();
+
+    options.allow_empty = args.flag_allow_empty;
+    options.max_message_len = args.flag_max_length.unwrap_or(po::MESSAGE_LIMIT);
+    options.gist_required = args.flag_gist_required;
+    options.retries = args.flag_retries;
+    options.github_token = args.flag_github_token.clone();
+    if let Some(ref ext) = args.flag_gist_ext {
+        options.gist_extension = ext.clone();
+    }
+    if let Some(ref url) = args.flag_paste_url {
+        options.snippet_backend = po::SnippetBackend::Paste { url: url.clone() };
+    }
+    options.insecure = args.flag_insecure;
+    options
+}
+
+// Render a human-readable preview of the notification our arguments describe
+fn preview(message: &str, args: &Args) -> String {
+    let mut body: String = message.chars().take(200).collect();
+    if message.chars().count() > 200 {
+        body.push_str("...");
+    }
+    format!("Title:    {}\nPriority: {}\nDevice:   {}\nMessage:\n{}",
+            args.flag_title.clone().unwrap_or("po".to_string()),
+            args.flag_p.unwrap_or(0),
+            args.flag_device.clone().unwrap_or("(all devices)".to_string()),
+            body)
+}
+
+// Show a preview of the notification and ask whether to send it, reading the
+// answer from the terminal, since standard input may be the message itself.
+fn confirm(message: &str, args: &Args) -> bool {
+    println!("{}", preview(message, args));
+    print!("Send this notification? [y/N] ");
+    std::io::stdout().flush().unwrap();
+
+    let mut answer = String::new();
+    let read = match std::fs::File::open(if cfg!(windows) { "CON" } else { "/dev/tty" }) {
+        Ok(tty) => std::io::BufReader::new(tty).read_line(&mut answer),
+        Err(_) => std::io::stdin().read_line(&mut answer)
+    };
+    match read {
+        Ok(_) => {
+            let answer = answer.trim().to_lowercase();
+            answer == "y" || answer == "yes"
+        },
+        Err(_) => false
+    }
+}
+
+// Upload the message ourselves in place of the Gist parameter, so that the
+// snippet's URL can be printed locally as well as linked in the notification,
+// returning the parameters to send and whether the message was uploaded. The
+// upload is retried as for push; if it still fails, we exit with
+// --gist-required, and otherwise leave the link out, like push.
+fn upload(message: &str, parameters: Vec<Parameters>, options: &po::Options,
+          quiet: bool) -> (Vec<Parameters>, bool) {
+    if !parameters.contains(&Parameters::Gist) {
+        return (parameters, false);
+    }
+
+    let mut title = "po".to_string();
+    for p in parameters.iter() {
+        if let Parameters::Title(ref t) = *p {
+            title = t.clone();
+        }
+    }
+
+    match po::upload_snippet_retrying(message, title, options) {
+        Ok((url, url_title)) => {
+            if !quiet {
+                writeln!(&mut std::io::stderr(), "po: uploaded to {}", url).unwrap();
+            }
+            let mut uploaded: Vec<Parameters> = parameters.into_iter().filter(|p| {
+                match *p {
+                    Parameters::Gist | Parameters::URL(_) |
+                    Parameters::URLTitle(_) => false,
+                    _ => true
+                }
+            }).collect();
+            uploaded.push(Parameters::URL(url));
+            uploaded.push(Parameters::URLTitle(url_title));
+            (uploaded, true)
+        },
+        Err(e) => {
+            writeln!(&mut std::io::stderr(), "po: couldn't upload full message: {}", e).unwrap();
+            if options.gist_required {
+                std::process::exit(1);
+            }
+            (parameters.into_iter().filter(|p| *p != Parameters::Gist).collect(), false)
+        }
+    }
+}
+
+// The exit status for --fail-on-suppress
+const SUPPRESSED_STATUS: i32 = 4;
+
+// Report on standard error that a message was deliberately not sent, and
+// why. With --fail-on-suppress, exit with SUPPRESSED_STATUS, except while
+// watching, where later lines can still be sent.
+fn suppressed(reason: &str, args: &Args) {
+    writeln!(&mut std::io::stderr(), "po: suppressed: {}", reason).unwrap();
+    if args.flag_fail_on_suppress && !args.flag_watch {
+        std::process::exit(SUPPRESSED_STATUS);
+    }
+}
+
+// Push a message with the parameters and options selected by our arguments,
+// skipping it if it duplicates one recorded in the dedup store within the
+// --dedup-window, and upload the message if --gist or --always-gist ask us to
+// push. If only a summary of the input is being sent, `full` is the whole input
+// to upload in its place.
+fn send(token: &str, user: &str, message: &str, full: Option<&str>, args: Args,
+        dedup_store: &Path) {
+    let mut args = args;
+    if args.flag_hostname {
+        args.flag_title = po::template::with_hostname(
+            args.flag_title.as_ref().map(|t| t.as_ref()));
+    }
+    if args.flag_preview {
+        // Show the body as Pushover would receive it: converted, then cut
+        // down to fit.
+        let rendered = if args.flag_markdown {
+            po::markdown::markdown_to_pushover_html(message)
+        }
+        else {
+            message.to_string()
+        };
+        let limit = args.flag_max_length.unwrap_or(po::MESSAGE_LIMIT);
+        let body: String = rendered.chars().take(limit).collect();
+        println!("{}", body);
+        return;
     }
-    if let Some(title) = args.flag_title {
-        parameters.push(Parameters::Title(title));
+    let dedup = args.flag_dedup_window.map(|window| {
+        let title = args.flag_title.as_ref().map(|t| t.as_ref());
+        (po::dedup::key(token, user, message, title), window)
+    });
+    if let Some((ref key, window)) = dedup {
+        if po::dedup::is_duplicate(dedup_store, key.as_ref(), window) {
+            suppressed(format!("duplicate within {}s window", window).as_ref(), &args);
+            return;
+        }
     }
-    if let Some(device) = args.flag_device {
-        parameters.push(Parameters::Device(device));
+    let wait_ack = args.flag_wait_ack;
+    let audit_log = args.flag_audit_log.clone();
+    let poll_interval = args.flag_poll_interval;
+    let ack_timeout = args.flag_ack_timeout;
+    let repeat = args.flag_repeat;
+    let interval = args.flag_interval;
+    let watching = args.flag_watch;
+    if wait_ack {
+        args.flag_p = Some(2);
     }
-    if let Some(sound) = args.flag_sound {
-        parameters.push(Parameters::Sound(sound));
+    if args.flag_confirm && !confirm(message, &args) {
+        suppressed("not confirmed", &args);
+        return;
     }
-    if args.flag_always_gist {
+    let arg_gist = args.flag_gist;
+    let markdown = args.flag_markdown;
+    let device = args.flag_device.clone();
+    let exclude_device = args.flag_exclude_device.clone();
+    let quiet = args.flag_quiet;
+    let no_truncate = args.flag_no_truncate;
+    let mut options = parse_options(&args);
+    let mut parameters = parse_parameters(args);
+    let full = full.unwrap_or(message);
+    if arg_gist && (full.chars().count() > options.max_message_len || full != message) {
         parameters.push(Parameters::Gist);
     }
-    if args.flag_debug {
-        parameters.push(Parameters::Debug);
+    if !exclude_device.is_empty() {
+        if device.is_some() {
+            println!("po: --device and --exclude-device can't be used together.");
+            std::process::exit(1);
+        }
+        let excluded: Vec<String> = exclude_device.iter()
+                                                  .map(|d| d.trim().to_string())
+                                                  .filter(|d| d != "")
+                                                  .collect();
+        match po::Client::with_options(token, user, options.clone())
+                  .devices_except(excluded.as_ref()) {
+            Ok(devices) => parameters.push(Parameters::Devices(devices)),
+            Err(errors) => {
+                println!("po: {:?}", errors);
+                std::process::exit(1);
+            }
+        }
+    }
+    if wait_ack {
+        // Emergency notifications need a retry interval and expiry; keep
+        // alerting until the ack timeout, up to Pushover's 3 hour maximum:
+        parameters.push(Parameters::Retry(60));
+        parameters.push(Parameters::Expire(std::cmp::min(ack_timeout, 10800) as u32));
+    }
+    let (mut parameters, uploaded) = upload(full, parameters, &options, quiet);
+    // Only truncate with --no-truncate when the full message is linked; an
+    // upload that failed leaves nothing to link:
+    if no_truncate && !uploaded {
+        options.overflow = po::Overflow::Error;
+    }
+    // Convert only what's sent, so that uploads keep the Markdown source:
+    let html: String;
+    let message: &str = if markdown {
+        parameters.push(Parameters::HTML);
+        html = po::markdown::markdown_to_pushover_html(message);
+        html.as_ref()
+    }
+    else {
+        message
+    };
+
+    let mut client = po::Client::with_options(token, user, options.clone());
+    for sent in 0..repeat {
+        if sent > 0 {
+            std::thread::sleep(std::time::Duration::from_secs(interval));
+        }
+        match client.send_detailed(message, parameters.as_ref()) {
+            Ok(outcome) => {
+                if let Some((ref key, window)) = dedup {
+                    po::dedup::record(dedup_store, key.as_ref(), window)
+                        .unwrap_or_else(|_| ());
+                }
+                if let Some(ref path) = audit_log {
+                    let entry = po::audit::Entry::new(message, parameters.as_ref(),
+                                                      outcome.request_id.as_ref());
+                    if po::audit::record(Path::new(path), &entry).is_err() {
+                        writeln!(&mut std::io::stderr(),
+                                 "po: couldn't write to the audit log {}", path).unwrap();
+                    }
+                }
+                if let (true, Some(receipt)) = (wait_ack, outcome.receipt) {
+                    wait_for_ack(&mut client, receipt.as_ref(), poll_interval, ack_timeout);
+                }
+            },
+            Err(ref errors) if options.overflow == po::Overflow::Error &&
+                               message.chars().count() > options.max_message_len => {
+                println!("po: {:?}", errors);
+                println!("po: use --gist to upload the full message and link it instead.");
+                std::process::exit(1);
+            },
+            Err(ref errors) if device.is_some() &&
+                               errors.iter().any(|e| e.contains("device")) => {
+                println!("po: {:?}", errors);
+                let device = device.unwrap();
+                if let Ok(devices) = po::validate_credentials_with_options(token, user,
+                                                                           &options) {
+                    if let Some(suggestion) = suggest_device(device.as_ref(), &devices) {
+                        println!("po: no device '{}'; did you mean '{}'?", device, suggestion);
+                    }
+                    else {
+                        println!("po: no device '{}'; your devices are: {}", device,
+                                 devices.join(", "));
+                    }
+                }
+                std::process::exit(1);
+            },
+            Err(errors) => {
+                println!("po: {:?}", errors);
+                // A failed send ends po, but not a --watch over one line:
+                if !watching {
+                    std::process::exit(1);
+                }
+                return;
+            }
+        }
+    }
+}
+
+// Poll an emergency notification's receipt every `poll_interval` seconds until
+// it's acknowledged, printing who acknowledged it, or exit with status 3 if it
+// expires or `timeout` seconds pass first.
+fn wait_for_ack(client: &mut po::Client, receipt: &str, poll_interval: u64, timeout: u64) {
+    let start = time::get_time().sec;
+
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(poll_interval));
+        match client.receipt_status(receipt) {
+            Ok(ref status) if status.acknowledged => {
+                println!("po: acknowledged by {} on {}", status.acknowledged_by,
+                         status.acknowledged_by_device);
+                return;
+            },
+            Ok(ref status) if status.expired => {
+                println!("po: expired without acknowledgement");
+                std::process::exit(3);
+            },
+            Ok(_) => {},
+            Err(errors) => println!("po: couldn't check receipt: {:?}", errors)
+        }
+        if (time::get_time().sec - start) as u64 >= timeout {
+            println!("po: not acknowledged within {} seconds", timeout);
+            std::process::exit(3);
+        }
+    }
+}
+
+// Delete the Gists po uploaded more than --gist-max-age days ago, printing
+// the ID of each one deleted.
+fn gist_cleanup(args: &Args) {
+    let github_token = match args.flag_github_token {
+        Some(ref token) => token.clone(),
+        None => {
+            println!("po: --gist-cleanup needs a GitHub token; set github_token in \
+                      the config or pass --github-token.");
+            std::process::exit(1);
+        }
+    };
+    let mut client = po::Client::with_options("", "", parse_options(args));
+
+    match client.cleanup_gists(github_token.as_ref(), args.flag_gist_max_age * 86400) {
+        Ok(deleted) => {
+            for id in deleted.iter() {
+                println!("po: deleted Gist {}", id);
+            }
+        },
+        Err(e) => {
+            println!("po: Gist cleanup failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The number of single-character insertions, deletions, and substitutions it
+// takes to turn one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for j in 0..b.len() {
+            let substitution = if ca == b[j] { diagonal } else { diagonal + 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = std::cmp::min(substitution,
+                                       std::cmp::min(row[j], row[j + 1]) + 1);
+        }
     }
-    parameters
+    row[b.len()]
+}
+
+// Find the device a mistyped device name was most likely meant to be: the
+// closest by edit distance, if it's within a third of the name's length, or
+// one the name is a prefix of.
+fn suggest_device(device: &str, devices: &[String]) -> Option<String> {
+    let device = device.to_lowercase();
+    let threshold = std::cmp::max(1, device.chars().count() / 3);
+
+    devices.iter()
+           .map(|d| {
+               let d_lower = d.to_lowercase();
+               let distance = if d_lower.starts_with(device.as_str()) {
+                   0
+               }
+               else {
+                   edit_distance(device.as_ref(), d_lower.as_ref())
+               };
+               (distance, d)
+           })
+           .filter(|&(distance, _)| distance <= threshold)
+           .min_by_key(|&(distance, _)| distance)
+           .map(|(_, d)| d.clone())
 }
 
 fn setup(config: &Path, token: &str, user: &str) {
     match po::config::write(token, user, config) {
         Ok(()) => {},
         Err(po::config::WriteError::InvalidApiToken(s)) => {
-            println!("Invalid API token {}", s);
+            println!("Invalid API token {} ({}). {}", s,
+                     describe_key(s.as_ref()), po::config::API_TOKEN_HELP);
             // TODO: setting exit status isn't stable yet
             // std::env::set_exit_status(2)
         },
         Err(po::config::WriteError::InvalidUserKey(s)) => {
-            println!("Invalid user key {}", s);
+            println!("Invalid user key {} ({}). {}", s,
+                     describe_key(s.as_ref()), po::config::USER_KEY_HELP);
             // TODO: setting exit status isn't stable yet
             // std::env::set_exit_status(2)
         },
@@ -95,18 +1060,271 @@ fn setup(config: &Path, token: &str, user: &str) {
     }
 }
 
-fn main() {
-    let args: Args = Docopt::new(USAGE)
-                        .and_then(|d| d.decode())
-                        .unwrap_or_else(|e| e.exit());
-    let mut config_path = std::env::home_dir().unwrap();
-    config_path.push(".config");
-    std::fs::create_dir(&config_path).unwrap_or_else(|_| ());
-    config_path.push("po");
-    std::fs::create_dir(&config_path).unwrap_or_else(|_| ());
+// Describe what's wrong with the format of a key, for error messages.
+fn describe_key(key: &str) -> String {
+    match po::config::key_problem(key) {
+        Some(problem) => problem.to_string(),
+        None => format!("{} characters", key.chars().count())
+    }
+}
+
+// Check the format of the given API token and user key, or if none are given
+// the stored ones, without contacting Pushover.
+fn check_format(config: &Path, args: &Args) {
+    let (token, user) = if args.arg_token != "" {
+        (args.arg_token.clone(), args.arg_user.clone())
+    }
+    else {
+        match po::config::read_config_or_system(config) {
+            Ok(c) => (c.token, c.user),
+            Err(e) => {
+                println!("config: FAILED ({:?})", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let report = po::config::check_format(token.as_ref(), user.as_ref());
+    match report.token {
+        Some(ref problem) => println!("token: FAILED ({})", problem),
+        None => println!("token: ok")
+    }
+    match report.user {
+        Some(ref problem) => println!("user: FAILED ({})", problem),
+        None => println!("user: ok")
+    }
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+}
+
+// Run each step of an end-to-end health check, printing a pass/fail line for
+// each and exiting non-zero on the first failure.
+fn check(config: &Path, args: &Args) {
+    let (token, user) = match po::config::read_config_or_system(config)
+                                  .map(|c| (c.token, c.user)) {
+        Ok(c) => {
+            println!("config: ok");
+            c
+        },
+        Err(e) => {
+            println!("config: FAILED ({:?})", e);
+            std::process::exit(1);
+        }
+    };
+
+    let options = parse_options(args);
+    match po::validate_credentials_with_options(token.as_ref(), user.as_ref(),
+                                                &options) {
+        Ok(devices) => {
+            println!("api: ok");
+            println!("credentials: ok ({} active devices)", devices.len());
+        },
+        Err(ref errors) if po::is_retryable(errors) => {
+            println!("api: FAILED ({:?})", errors);
+            std::process::exit(1);
+        },
+        Err(errors) => {
+            println!("api: ok");
+            println!("credentials: FAILED ({:?})", errors);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Find the config file: $PO_CONFIG if it's set, or else tokens.json in the
+// platform's config directory for po ($XDG_CONFIG_HOME/po on Linux,
+// ~/Library/Application Support/po on macOS, %APPDATA%\po\config on
+// Windows), creating its directories as needed. A config left at the old
+// ~/.config/po/tokens.json is still used if there isn't one there yet.
+// Returns None if there's no home directory to put it in. Reading falls back
+// to the system-wide config (see po::config::system_path); --setup doesn't.
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("PO_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let mut config_path = match ProjectDirs::from("", "", "po") {
+        Some(dirs) => dirs.config_dir().to_path_buf(),
+        None => return None
+    };
+    std::fs::create_dir_all(&config_path).unwrap_or_else(|_| ());
     config_path.push("tokens");
     config_path.set_extension("json");
 
+    if !config_path.exists() {
+        if let Some(home) = dirs::home_dir() {
+            let legacy = home.join(".config").join("po").join("tokens.json");
+            if legacy.exists() {
+                return Some(legacy);
+            }
+        }
+    }
+    Some(config_path)
+}
+
+// The options listed in USAGE, as their short name, long name, and whether
+// they take an argument, so completions can't fall out of date with it.
+fn usage_options() -> Vec<(Option<String>, Option<String>, bool)> {
+    let mut options = Vec::new();
+    let section = match USAGE.find("Options:") {
+        Some(start) => &USAGE[start..],
+        None => return options
+    };
+
+    for line in section.lines().filter(|l| l.starts_with("    -")) {
+        let line = line.trim_left();
+        let spec = match line.find("  ") {
+            Some(end) => &line[..end],
+            None => line
+        };
+        let mut short = None;
+        let mut long = None;
+        let mut takes_arg = false;
+
+        for name in spec.split(", ") {
+            let mut words = name.split_whitespace();
+            let flag = words.next().unwrap_or("");
+            takes_arg = takes_arg || words.next().is_some();
+            if flag.starts_with("--") {
+                long = Some(flag[2..].to_string());
+            }
+            else {
+                short = Some(flag[1..].to_string());
+            }
+        }
+        options.push((short, long, takes_arg));
+    }
+    options
+}
+
+// A completion script for the given shell, completing every option, the
+// sound names for --sound, and the priorities for -p.
+fn completions(shell: &str) -> Option<String> {
+    let options = usage_options();
+    let sounds: Vec<&str> = po::SOUNDS.iter().map(|&(sound, _)| sound).collect();
+    let sounds = sounds.join(" ");
+    let mut flags: Vec<String> = Vec::new();
+
+    for &(ref short, ref long, _) in options.iter() {
+        if let Some(ref short) = *short {
+            flags.push(format!("-{}", short));
+        }
+        if let Some(ref long) = *long {
+            flags.push(format!("--{}", long));
+        }
+    }
+    let flags = flags.join(" ");
+
+    match shell {
+        "bash" => Some(format!("\
+_po() {{
+    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"
+    case \"${{COMP_WORDS[COMP_CWORD-1]}}\" in
+        -s|--sound) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;
+        -p) COMPREPLY=($(compgen -W \"-2 -1 0 1 2\" -- \"$cur\")); return ;;
+        --completions) COMPREPLY=($(compgen -W \"bash zsh fish\" -- \"$cur\")); return ;;
+    esac
+    COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))
+}}
+complete -o default -F _po po
+", sounds, flags)),
+        "zsh" => Some(format!("\
+#compdef po
+_po() {{
+    case \"${{words[CURRENT-1]}}\" in
+        -s|--sound) compadd -- {} ;;
+        -p) compadd -- -2 -1 0 1 2 ;;
+        --completions) compadd -- bash zsh fish ;;
+        *) compadd -- {}; _files ;;
+    esac
+}}
+_po \"$@\"
+", sounds, flags)),
+        "fish" => {
+            let mut script = String::new();
+            for &(ref short, ref long, takes_arg) in options.iter() {
+                let mut line = "complete -c po".to_string();
+                if let Some(ref short) = *short {
+                    line.push_str(format!(" -s {}", short).as_ref());
+                }
+                if let Some(ref long) = *long {
+                    line.push_str(format!(" -l {}", long).as_ref());
+                }
+                let short_ref: Option<&str> = short.as_ref().map(|s| s.as_ref());
+                let long_ref: Option<&str> = long.as_ref().map(|l| l.as_ref());
+                match (short_ref, long_ref) {
+                    (_, Some("sound")) => {
+                        line.push_str(format!(" -x -a \"{}\"", sounds).as_ref())
+                    },
+                    (Some("p"), _) => line.push_str(" -x -a \"-2 -1 0 1 2\""),
+                    (_, Some("completions")) => line.push_str(" -x -a \"bash zsh fish\""),
+                    _ if takes_arg => line.push_str(" -r"),
+                    _ => {}
+                }
+                script.push_str(line.as_ref());
+                script.push('\n');
+            }
+            Some(script)
+        },
+        _ => None
+    }
+}
+
+fn main() {
+    let mut args: Args = Docopt::new(USAGE)
+                            .and_then(|d| d.decode())
+                            .unwrap_or_else(|e| e.exit());
+    if args.flag_silent_log {
+        args.flag_p = Some(-2);
+    }
+    if args.flag_debug {
+        log::set_logger(|max_level| {
+            max_level.set(LogLevelFilter::Debug);
+            Box::new(StderrLogger)
+        }).unwrap();
+    }
+    if args.flag_insecure {
+        writeln!(&mut std::io::stderr(),
+                 "po: WARNING: --insecure disables TLS certificate verification; \
+                  requests can be intercepted and credentials exposed.").unwrap();
+    }
+    let config_path = match config_path() {
+        Some(path) => path,
+        None => {
+            println!("po: Couldn't find your home directory; set PO_CONFIG to the \
+                      path of a config file to use instead.");
+            std::process::exit(2);
+        }
+    };
+    let dedup_store: PathBuf = config_path.with_file_name("dedup.json");
+
+    if args.flag_help_priorities {
+        for &(priority, meaning) in po::PRIORITIES.iter() {
+            println!("{:>3}  {}", priority, meaning);
+        }
+        return;
+    }
+    if args.flag_help_sounds {
+        for &(sound, description) in po::SOUNDS.iter() {
+            println!("{:<14}{}", sound, description);
+        }
+        return;
+    }
+
+    if let Some(shell) = args.flag_completions.clone() {
+        match completions(shell.as_ref()) {
+            Some(script) => print!("{}", script),
+            None => {
+                writeln!(&mut std::io::stderr(),
+                         "po: No completions for {}; use bash, zsh, or fish.",
+                         shell).unwrap();
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     if args.flag_setup {
         if args.arg_token == "" || args.arg_user == "" {
             println!("
@@ -124,8 +1342,16 @@ at https://pushover.net to get your user key. Finally, run the command:
         }
         return;
     }
+    if args.flag_check {
+        check(&config_path, &args);
+        return;
+    }
+    if args.flag_check_format {
+        check_format(&config_path, &args);
+        return;
+    }
 
-    let config = po::config::read(&config_path);
+    let config = po::config::read_config_or_system(&config_path);
 
     if config == Err(po::config::ReadError::NoConfig) {
         println!("po: Please run po --setup to configure your Pushover API token & user key.");
@@ -137,49 +1363,129 @@ at https://pushover.net to get your user key. Finally, run the command:
         // TODO: setting exit status isn't stable yet
         // std::env::set_exit_status(1);
     }
-    else if let Some(message) = args.arg_message.clone() {
-        let (token, user) = config.unwrap();
-        let arg_gist = args.flag_gist;
-        let mut parameters = parse_parameters(args);
-        if arg_gist && message.len() > 1024 {
-            parameters.push(Parameters::Gist);
-        }
+    else if let Some(tag) = args.flag_cancel_tag.clone() {
+        let token = config.unwrap().token;
+        let options = parse_options(&args);
 
-        match po::push(token.as_ref(),
-                       user.as_ref(),
-                       message.as_ref(),
-                       parameters.as_ref()) {
+        match po::cancel_by_tag_with_options(token.as_ref(), tag.as_ref(),
+                                             &options) {
             Ok(()) => {},
             Err(errors) => {
                 println!("po: {:?}", errors);
-                // TODO: setting exit status isn't stable yet
-                // std::env::set_exit_status(1);
+                std::process::exit(1);
             }
         }
     }
+    else if args.flag_glance {
+        let config = config.unwrap();
+        let glance = po::Glance {
+            title: args.flag_title.clone(),
+            text: args.arg_message.clone(),
+            subtext: args.flag_subtext.clone(),
+            count: args.flag_count,
+            percent: args.flag_percent
+        };
+        let mut client = po::Client::with_options(config.token.as_ref(), config.user.as_ref(),
+                                                  parse_options(&args));
+
+        if let Err(errors) = client.update_glance(&glance) {
+            println!("po: {:?}", errors);
+            std::process::exit(1);
+        }
+    }
+    else if args.flag_gist_cleanup {
+        let config = config.unwrap();
+        let mut args = args;
+        apply_config(&mut args, &config, None);
+        gist_cleanup(&args);
+    }
+    else if args.flag_watch {
+        let config = config.unwrap();
+        let mut args = args;
+        // Each line's severity rules take precedence over only the config's
+        // defaults, so apply the profile before deciding:
+        if let Some(path) = args.flag_params_file.take() {
+            apply_profile(&mut args, path.as_ref());
+        }
+        let priority_given = args.flag_p.is_some();
+        apply_config(&mut args, &config, None);
+        watch(&config, args, priority_given, &dedup_store);
+    }
+    else if args.flag_wrap {
+        let config = config.unwrap();
+        let mut args = args;
+        apply_config(&mut args, &config, None);
+        let message = wrap(&mut args);
+        send(config.token.as_ref(), config.user.as_ref(), message.as_ref(), None,
+             args, &dedup_store);
+    }
+    else if let Some(message) = args.arg_message.clone() {
+        let config = config.unwrap();
+        let mut args = args;
+        apply_config(&mut args, &config, None);
+        send(config.token.as_ref(), config.user.as_ref(), message.as_ref(), None,
+             args, &dedup_store);
+    }
+    else if args.flag_head.is_some() || args.flag_tail.is_some() {
+        let config = config.unwrap();
+        let keep_full = args.flag_gist || args.flag_always_gist;
+        let (summary, full) = read_summary(args.flag_head.unwrap_or(0),
+                                           args.flag_tail.unwrap_or(0),
+                                           keep_full);
+        let mut args = args;
+        apply_config(&mut args, &config, None);
+        send(config.token.as_ref(), config.user.as_ref(), summary.as_ref(),
+             full.as_ref().map(|f| f.as_ref()), args, &dedup_store);
+    }
     else {
-        let (token, user) = config.unwrap();
-        let mut input = std::io::stdin();
-        let mut message = String::new();
-
-        input.read_to_string(&mut message).unwrap();
-        print!("{}", message); // TODO: use tee instead when that stabilizes
-        let arg_gist = args.flag_gist;
-        let mut parameters = parse_parameters(args);
-        if arg_gist && message.len() > 1024 {
-            parameters.push(Parameters::Gist);
-        }
-
-        match po::push(token.as_ref(),
-                       user.as_ref(),
-                       message.as_ref(),
-                       parameters.as_ref()) {
-            Ok(()) => {},
-            Err(errors) => {
-                println!("po: {:?}", errors);
-                // TODO: setting exit status isn't stable yet
-                // std::env::set_exit_status(1);
+        let config = config.unwrap();
+        // Unless the message may be uploaded, only as much of it is kept as
+        // could be sent, with room to spare for headers and a title line and
+        // so that it's still truncated (or refused) as too long:
+        let limit = if args.flag_gist || args.flag_always_gist ||
+                       args.flag_stdin_binary_safe {
+            None
+        }
+        else {
+            let max_length = args.flag_max_length
+                                 .or(config.max_message_len)
+                                 .unwrap_or(po::MESSAGE_LIMIT);
+            Some(4 * max_length + STDIN_SLACK)
+        };
+        let raw = read_stdin(limit);
+        if args.flag_stdin_binary_safe && std::str::from_utf8(&raw).is_err() {
+            let mut args = args;
+            apply_config(&mut args, &config, None);
+            args.flag_gist = true;
+            // The summary says the input was uploaded, so it mustn't be sent
+            // if the upload fails:
+            args.flag_gist_required = true;
+            if args.flag_gist_ext.is_none() {
+                args.flag_gist_ext = Some(".b64".to_string());
+            }
+            let (summary, full) = binary_summary(&raw);
+            send(config.token.as_ref(), config.user.as_ref(), summary.as_ref(),
+                 Some(full.as_ref()), args, &dedup_store);
+            return;
+        }
+        // Log streams often contain stray non-UTF-8 bytes; replace them
+        // rather than refusing the whole message:
+        let mut message = String::from_utf8_lossy(&raw).into_owned();
+        let mut args = args;
+        if args.flag_parse_headers {
+            let (headers, body) = split_headers(message.as_ref());
+            apply_headers(&mut args, headers);
+            message = body;
+        }
+        if args.flag_title_from_first_line {
+            let (title, body) = split_title(message.as_ref());
+            if args.flag_title.is_none() {
+                args.flag_title = Some(title);
             }
+            message = body;
         }
+        apply_config(&mut args, &config, Some(message.as_ref()));
+        send(config.token.as_ref(), config.user.as_ref(), message.as_ref(), None,
+             args, &dedup_store);
     }
 }
@@ -1,13 +1,95 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
 use std::io::prelude::*;
+use std::env;
 use std::path;
-use std::fs::File;
-use rustc_serialize::json;
+use std::fs::{self, File};
+use std::process;
+use rustc_serialize::json::{self, Json};
+#[cfg(feature = "encryption")]
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+#[cfg(feature = "encryption")]
+use ring::{aead, pbkdf2};
+#[cfg(feature = "encryption")]
+use ring::rand::{SecureRandom, SystemRandom};
+#[cfg(feature = "encryption")]
+use std::num::NonZeroU32;
+#[cfg(feature = "encryption")]
+use std::str;
 use regex::Regex;
 
-#[derive(RustcEncodable, RustcDecodable)]
-struct Config {
-    token: String,
-    user: String
+/// The contents of a po config file. Every field besides the token and user
+/// key is optional, so older config files still parse.
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq, Clone)]
+pub struct Config {
+    /// The Pushover API token
+    pub token: String,
+    /// The Pushover user key
+    pub user: String,
+    /// The sound to play for each priority when no sound is given
+    pub default_sounds: Option<BTreeMap<i8, String>>,
+    /// The priority to send with when no priority is given
+    pub default_priority: Option<i8>,
+    /// The device to send to when no device is given
+    pub default_device: Option<String>,
+    /// Rules for choosing a message's priority from its contents
+    pub severity_rules: Option<Vec<SeverityRule>>,
+    /// The title to send with when no title is given, as a template (see
+    /// `template::expand`), such as "{hostname} at {time}"
+    pub title_template: Option<String>,
+    /// The longest message, in characters, that the account's Pushover plan
+    /// accepts (see `Options::max_message_len`)
+    pub max_message_len: Option<usize>,
+    /// A file to append a line to for every notification sent (see
+    /// `audit::Entry`)
+    pub audit_log: Option<path::PathBuf>,
+    /// A GitHub personal access token to upload Gists with (see
+    /// `Options::github_token`), so that they can later be cleaned up
+    pub github_token: Option<String>
+}
+
+// Every field of the current config format, for telling whether a config file
+// needs upgrading
+const FIELDS: &'static [&'static str] = &[
+    "token", "user", "default_sounds", "default_priority", "default_device",
+    "severity_rules", "title_template", "max_message_len",
+    "audit_log", "github_token"
+];
+
+/// Sends messages matching a regular expression with the given priority
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq, Clone)]
+pub struct SeverityRule {
+    /// The regular expression to match messages against
+    pub pattern: String,
+    /// The priority to send matching messages with
+    pub priority: i8
+}
+
+impl Config {
+    /// Returns the configured default sound for a priority, if any.
+    pub fn default_sound(&self, priority: i8) -> Option<String> {
+        match self.default_sounds {
+            Some(ref sounds) => sounds.get(&priority).cloned(),
+            None => None
+        }
+    }
+
+    /// Returns the priority of the first severity rule matching a message,
+    /// if any. Rules with invalid patterns never match.
+    pub fn severity(&self, message: &str) -> Option<i8> {
+        match self.severity_rules {
+            Some(ref rules) => {
+                rules.iter().find(|rule| {
+                    match Regex::new(rule.pattern.as_ref()) {
+                        Ok(re) => re.is_match(message),
+                        Err(_) => false
+                    }
+                }).map(|rule| rule.priority)
+            },
+            None => None
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -16,7 +98,10 @@ pub enum ReadError {
     JsonError,
     FileError,
     InvalidApiToken(String),
-    InvalidUserKey(String)
+    InvalidUserKey(String),
+    /// An encrypted config couldn't be decrypted, such as with the wrong
+    /// passphrase
+    Decryption
 }
 
 #[derive(Debug, PartialEq)]
@@ -26,53 +111,446 @@ pub enum WriteError {
     FileError
 }
 
-fn valid_token(token: &str) -> bool {
-    let re = Regex::new(r"[A-Za-z0-9]").unwrap();
-    token.len() == 30 && re.is_match(token)
+// A missing config file is NoConfig, so that po can prompt for --setup; any
+// other I/O failure is a FileError.
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> ReadError {
+        match e.kind() {
+            io::ErrorKind::NotFound => ReadError::NoConfig,
+            _ => ReadError::FileError
+        }
+    }
+}
+
+impl From<json::DecoderError> for ReadError {
+    fn from(_: json::DecoderError) -> ReadError {
+        ReadError::JsonError
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(_: io::Error) -> WriteError {
+        WriteError::FileError
+    }
+}
+
+// Pushover API tokens, user keys, and group keys are all 30 letters and
+// numbers.
+fn valid_key(key: &str) -> bool {
+    let re = Regex::new(r"^[A-Za-z0-9]{30}$").unwrap();
+    re.is_match(key)
+}
+
+/// Returns true if a string has the format of a Pushover API token.
+pub fn valid_api_token(token: &str) -> bool {
+    valid_key(token)
+}
+
+/// Returns true if a string has the format of a Pushover user or group key.
+pub fn valid_user_key(user: &str) -> bool {
+    valid_key(user)
+}
+
+/// Why a string doesn't have the format of a Pushover key
+#[derive(PartialEq, Clone, Debug)]
+pub enum KeyProblem {
+    /// It has a character other than a letter or number, the first of which
+    /// is given
+    Character(char),
+    /// It's made of letters and numbers, but has the given number of
+    /// characters instead of 30
+    Length(usize)
+}
+
+impl fmt::Display for KeyProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyProblem::Character(c) => write!(f, "contains {:?}", c),
+            KeyProblem::Length(n) => write!(f, "{} characters instead of 30", n)
+        }
+    }
+}
+
+/// Returns what's wrong with the format of a Pushover key, if anything.
+pub fn key_problem(key: &str) -> Option<KeyProblem> {
+    let bad = key.chars().find(|&c| match c {
+        'A'...'Z' | 'a'...'z' | '0'...'9' => false,
+        _ => true
+    });
+    match bad {
+        Some(c) => Some(KeyProblem::Character(c)),
+        None if key.chars().count() != 30 => Some(KeyProblem::Length(key.chars().count())),
+        None => None
+    }
+}
+
+/// The format problems with an API token and user key, as found by
+/// `check_format`
+#[derive(PartialEq, Clone, Debug)]
+pub struct FormatReport {
+    pub token: Option<KeyProblem>,
+    pub user: Option<KeyProblem>
 }
 
+impl FormatReport {
+    /// Returns true if both keys have the right format.
+    pub fn is_ok(&self) -> bool {
+        self.token.is_none() && self.user.is_none()
+    }
+}
+
+/// Checks the format of an API token and user key without contacting
+/// Pushover, for catching typos before making any request; the keys may
+/// still be rejected by `validate_credentials`.
+pub fn check_format(token: &str, user: &str) -> FormatReport {
+    FormatReport {
+        token: key_problem(token),
+        user: key_problem(user)
+    }
+}
+
+/// What a valid API token looks like, and where to get one
+pub const API_TOKEN_HELP: &'static str =
+    "API tokens are 30 letters and numbers; create an application at \
+     https://pushover.net/apps/build to get one.";
+
+/// What a valid user or group key looks like, and where to find it
+pub const USER_KEY_HELP: &'static str =
+    "User and group keys are 30 letters and numbers; your user key is shown \
+     on your dashboard at https://pushover.net, and group keys at \
+     https://pushover.net/groups.";
+
 pub fn read(path: &path::Path) -> Result<(String, String), ReadError> {
-    let file = File::open(path);
+    read_config(path).map(|config| (config.token, config.user))
+}
 
-    match file {
-        Ok(mut f) => {
-            let mut buf = String::new();
-            match f.read_to_string(&mut buf) {
-                Ok(_) => {
-                    let config: Config = json::decode(&buf).unwrap();
-                    Ok((config.token, config.user))
-                },
-                Err(_) => Err(ReadError::FileError)
-            }
+/// Like `read`, but returns the whole config, including its optional fields.
+/// A config file in an older format is upgraded in place (see `migrate`).
+pub fn read_config(path: &path::Path) -> Result<Config, ReadError> {
+    let (config, outdated) = try!(load(path));
+
+    if outdated {
+        // The config is still usable in its old format, so a failed upgrade
+        // (such as of a read-only config) is only worth a warning.
+        if let Err(e) = write_config(&config, path) {
+            warn!("couldn't upgrade config file {}: {:?}", path.display(), e);
+        }
+    }
+    Ok(config)
+}
+
+/// The system-wide config file, which users without a config file of their
+/// own inherit, such as one an admin provisions with a shared API token on a
+/// multi-user server: $PO_SYSTEM_CONFIG if it's set, or else
+/// /etc/po/tokens.json (%PROGRAMDATA%\po\tokens.json on Windows).
+pub fn system_path() -> path::PathBuf {
+    if let Some(path) = env::var_os("PO_SYSTEM_CONFIG") {
+        return path::PathBuf::from(path);
+    }
+    if cfg!(windows) {
+        let data = env::var_os("PROGRAMDATA").unwrap_or("C:\\ProgramData".into());
+        path::PathBuf::from(data).join("po").join("tokens.json")
+    }
+    else {
+        path::PathBuf::from("/etc/po/tokens.json")
+    }
+}
+
+/// Like `read_config`, but falls back to the system-wide config (see
+/// `system_path`) if there's no config file at the path. The system-wide
+/// config belongs to the admin, so it's read as it is and never upgraded.
+pub fn read_config_or_system(path: &path::Path) -> Result<Config, ReadError> {
+    match read_config(path) {
+        Err(ReadError::NoConfig) => {
+            load(system_path().as_ref()).map(|(config, _)| config)
         },
-        Err(_) => Err(ReadError::NoConfig)
+        result => result
+    }
+}
+
+/// Upgrades a config file written by an older version of po to the current
+/// format, filling in defaults for the fields it lacks and keeping the API
+/// token and user key exactly as they are. Returns whether the file needed
+/// upgrading.
+pub fn migrate(path: &path::Path) -> Result<bool, ReadError> {
+    let (config, outdated) = try!(load(path));
+
+    if outdated {
+        try!(write_config(&config, path).map_err(|_| ReadError::FileError));
     }
+    Ok(outdated)
+}
+
+// Read and decode a config file, also returning whether it's missing any of
+// the fields of the current format.
+fn load(path: &path::Path) -> Result<(Config, bool), ReadError> {
+    let mut f = try!(File::open(path));
+    let mut buf = String::new();
+
+    try!(f.read_to_string(&mut buf));
+    let config: Config = try!(json::decode(&buf));
+    let outdated = match Json::from_str(&buf) {
+        Ok(Json::Object(fields)) => {
+            FIELDS.iter().any(|field| !fields.contains_key(*field))
+        },
+        _ => false
+    };
+    Ok((config, outdated))
 }
 
+/// Writes a config file with the given API token and user key, keeping any
+/// optional fields already set in an existing config file at the path.
 pub fn write(token: &str, user: &str,
              path: &path::Path) -> Result<(), WriteError> {
-    if !valid_token(token) {
-        Err(WriteError::InvalidApiToken(token.to_string()))
+    let config = match read_config(path) {
+        Ok(existing) => Config {
+            token: token.to_string(),
+            user: user.to_string(),
+            ..existing
+        },
+        Err(_) => Config {
+            token: token.to_string(),
+            user: user.to_string(),
+            default_sounds: None,
+            default_priority: None,
+            default_device: None,
+            severity_rules: None,
+            title_template: None,
+            max_message_len: None,
+            audit_log: None,
+            github_token: None
+        }
+    };
+    write_config(&config, path)
+}
+
+/// Writes a whole config, including its optional fields.
+pub fn write_config(config: &Config,
+                    path: &path::Path) -> Result<(), WriteError> {
+    try!(validate(config));
+    let config_json = json::encode(config).unwrap();
+    write_atomic(config_json.as_bytes(), path)
+}
+
+// Check a config's API token and user key before writing it.
+fn validate(config: &Config) -> Result<(), WriteError> {
+    if !valid_api_token(config.token.as_ref()) {
+        Err(WriteError::InvalidApiToken(config.token.clone()))
     }
-    else if !valid_token(user) {
-        Err(WriteError::InvalidUserKey(user.to_string()))
+    else if !valid_user_key(config.user.as_ref()) {
+        Err(WriteError::InvalidUserKey(config.user.clone()))
     }
     else {
-        let config = Config {
-            token: token.to_string(),
-            user: user.to_string()
-        };
-        let config_json = json::encode(&config).unwrap();
-
-        let file = File::create(path);
-        match file {
-            Ok(mut f) => {
-                match f.write_all(config_json.into_bytes().as_ref()) {
-                    Ok(_) => Ok(()),
-                    Err(_) => Err(WriteError::FileError)
-                }
-            },
-            Err(_) => Err(WriteError::FileError)
+        Ok(())
+    }
+}
+
+// Write to a temporary file next to the config and rename it into place, so
+// that concurrent writers or a write interrupted partway through never leave
+// a corrupt config behind.
+fn write_atomic(contents: &[u8], path: &path::Path) -> Result<(), WriteError> {
+    let temp_path = match path.file_name() {
+        Some(name) => path.with_file_name(
+            format!(".{}.{}.tmp", name.to_string_lossy(), process::id())),
+        None => return Err(WriteError::FileError)
+    };
+    let file = create_private(&temp_path);
+    let written = match file {
+        Ok(mut f) => {
+            f.write_all(contents)
+             .and_then(|_| f.sync_all())
+             .and_then(|_| keep_permissions(&temp_path, path))
+        },
+        Err(_) => return Err(WriteError::FileError)
+    };
+    match written.and_then(|_| fs::rename(&temp_path, path)) {
+        Ok(_) => Ok(()),
+        Err(_) => {
+            fs::remove_file(&temp_path).unwrap_or_else(|_| ());
+            Err(WriteError::FileError)
         }
     }
 }
+
+// Create a file only its owner can read, since configs hold credentials.
+#[cfg(unix)]
+fn create_private(path: &path::Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new().write(true).create(true).truncate(true)
+                          .mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &path::Path) -> io::Result<File> {
+    File::create(path)
+}
+
+// Give the temporary file the permissions of the config it's replacing, so
+// that a rename doesn't change who can read the config.
+fn keep_permissions(temp_path: &path::Path,
+                    path: &path::Path) -> io::Result<()> {
+    match fs::metadata(path) {
+        Ok(metadata) => fs::set_permissions(temp_path, metadata.permissions()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+    }
+}
+
+// An encrypted config file: the config's JSON sealed with ChaCha20-Poly1305
+// under a key derived from a passphrase with PBKDF2-HMAC-SHA256, with each
+// field base64-encoded.
+#[cfg(feature = "encryption")]
+#[derive(RustcEncodable, RustcDecodable)]
+struct EncryptedConfig {
+    salt: String,
+    nonce: String,
+    ciphertext: String
+}
+
+#[cfg(feature = "encryption")]
+const PBKDF2_ITERATIONS: u32 = 100000;
+
+#[cfg(feature = "encryption")]
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> aead::LessSafeKey {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256,
+                   NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+                   salt, passphrase.as_bytes(), &mut key);
+    aead::LessSafeKey::new(aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key).unwrap())
+}
+
+/// Like `write_config`, but encrypts the config with a passphrase, for
+/// keeping the API token and user key unreadable on shared machines. Read it
+/// back with `read_encrypted`.
+#[cfg(feature = "encryption")]
+pub fn write_encrypted(config: &Config, path: &path::Path,
+                       passphrase: &str) -> Result<(), WriteError> {
+    try!(validate(config));
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    try!(rng.fill(&mut salt).map_err(|_| WriteError::FileError));
+    try!(rng.fill(&mut nonce).map_err(|_| WriteError::FileError));
+
+    let mut sealed = json::encode(config).unwrap().into_bytes();
+    try!(passphrase_key(passphrase, &salt)
+             .seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce),
+                                       aead::Aad::empty(), &mut sealed)
+             .map_err(|_| WriteError::FileError));
+    let encrypted = EncryptedConfig {
+        salt: salt.to_base64(base64::STANDARD),
+        nonce: nonce.to_base64(base64::STANDARD),
+        ciphertext: sealed.to_base64(base64::STANDARD)
+    };
+    write_atomic(json::encode(&encrypted).unwrap().as_bytes(), path)
+}
+
+/// Reads a config file written by `write_encrypted`, decrypting it with the
+/// passphrase. A wrong passphrase is a `ReadError::Decryption`.
+#[cfg(feature = "encryption")]
+pub fn read_encrypted(path: &path::Path, passphrase: &str) -> Result<Config, ReadError> {
+    let mut f = try!(File::open(path));
+    let mut buf = String::new();
+    try!(f.read_to_string(&mut buf));
+    let encrypted: EncryptedConfig = try!(json::decode(&buf));
+
+    let salt = try!(encrypted.salt.from_base64().map_err(|_| ReadError::JsonError));
+    let nonce = try!(encrypted.nonce.from_base64().map_err(|_| ReadError::JsonError));
+    let nonce = try!(aead::Nonce::try_assume_unique_for_key(&nonce)
+                         .map_err(|_| ReadError::JsonError));
+    let mut sealed = try!(encrypted.ciphertext.from_base64()
+                                   .map_err(|_| ReadError::JsonError));
+    let plain = try!(passphrase_key(passphrase, &salt)
+                         .open_in_place(nonce, aead::Aad::empty(), &mut sealed)
+                         .map_err(|_| ReadError::Decryption));
+    let plain = try!(str::from_utf8(plain).map_err(|_| ReadError::Decryption));
+    Ok(try!(json::decode(plain)))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{read_config, read_config_or_system, write_atomic};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path;
+
+    // A path in the temporary directory that no other test uses.
+    fn temp_config(name: &str) -> path::PathBuf {
+        let path = env::temp_dir().join(format!("po-test-{}-{}.json", name,
+                                                super::process::id()));
+        fs::remove_file(&path).unwrap_or_else(|_| ());
+        path
+    }
+
+    fn mode(path: &path::Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    fn contents(path: &path::Path) -> String {
+        let mut buf = String::new();
+        File::open(path).unwrap().read_to_string(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn new_configs_are_private() {
+        let path = temp_config("new");
+        write_atomic(b"{}", &path).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(contents(&path), "{}");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rewriting_keeps_permissions() {
+        let path = temp_config("private");
+        File::create(&path).unwrap().write_all(b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        write_atomic(b"new", &path).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(contents(&path), "new");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn migrating_on_read_keeps_permissions() {
+        let path = temp_config("migrate");
+        File::create(&path).unwrap()
+            .write_all(b"{\"token\":\"azGDORePK8gMaC0QOYAMyEEuzJnyUi\",\
+                         \"user\":\"uQiRzpo4DXghDmr9QzzfQu27cmVRsG\"}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let config = read_config(&path).unwrap();
+        assert_eq!(config.token, "azGDORePK8gMaC0QOYAMyEEuzJnyUi");
+        assert!(contents(&path).contains("\"github_token\""));
+        assert_eq!(mode(&path), 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn system_config_is_not_upgraded() {
+        let path = temp_config("missing");
+        let system = temp_config("system");
+        let old = "{\"token\":\"azGDORePK8gMaC0QOYAMyEEuzJnyUi\",\
+                   \"user\":\"uQiRzpo4DXghDmr9QzzfQu27cmVRsG\"}";
+        File::create(&system).unwrap().write_all(old.as_bytes()).unwrap();
+        env::set_var("PO_SYSTEM_CONFIG", &system);
+        let config = read_config_or_system(&path).unwrap();
+        env::remove_var("PO_SYSTEM_CONFIG");
+        assert_eq!(config.user, "uQiRzpo4DXghDmr9QzzfQu27cmVRsG");
+        assert_eq!(contents(&system), old);
+        fs::remove_file(&system).unwrap();
+    }
+
+    #[test]
+    fn rewriting_keeps_shared_permissions() {
+        let path = temp_config("shared");
+        File::create(&path).unwrap().write_all(b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomic(b"new", &path).unwrap();
+        assert_eq!(mode(&path), 0o640);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+//! A small on-disk store of recently sent messages, used to suppress
+//! duplicate notifications within a time window.
+
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher, SipHasher};
+use std::io::prelude::*;
+use std::fs::File;
+use std::path;
+use rustc_serialize::json;
+use time;
+
+/// Returns a key identifying a message by a hash of its API token, user key,
+/// message body, and title.
+pub fn key(token: &str, user: &str, message: &str, title: Option<&str>) -> String {
+    let mut hasher = SipHasher::new();
+
+    token.hash(&mut hasher);
+    user.hash(&mut hasher);
+    message.hash(&mut hasher);
+    title.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+// Read the store of keys and the Unix timestamps they were last sent at; a
+// missing or unreadable store is treated as empty.
+fn read(path: &path::Path) -> BTreeMap<String, i64> {
+    let mut buf = String::new();
+
+    match File::open(path) {
+        Ok(mut f) => {
+            if f.read_to_string(&mut buf).is_err() {
+                return BTreeMap::new();
+            }
+            json::decode(&buf).unwrap_or(BTreeMap::new())
+        },
+        Err(_) => BTreeMap::new()
+    }
+}
+
+/// Returns true if a message with the given key was recorded as sent within
+/// the last `window` seconds.
+pub fn is_duplicate(path: &path::Path, key: &str, window: i64) -> bool {
+    let now = time::get_time().sec;
+
+    match read(path).get(key) {
+        Some(&sent) => now - sent < window,
+        None => false
+    }
+}
+
+/// Records a message with the given key as sent now, dropping any entries
+/// older than `window` seconds from the store.
+pub fn record(path: &path::Path, key: &str, window: i64) -> Result<(), ()> {
+    let now = time::get_time().sec;
+    let mut store: BTreeMap<String, i64> = read(path).into_iter()
+                                                     .filter(|&(_, sent)| now - sent < window)
+                                                     .collect();
+    store.insert(key.to_string(), now);
+
+    let store_json = try!(json::encode(&store).map_err(|_| ()));
+    match File::create(path) {
+        Ok(mut f) => f.write_all(store_json.as_bytes()).map_err(|_| ()),
+        Err(_) => Err(())
+    }
+}
@@ -0,0 +1,199 @@
+//! The message flags of the po command line, and the `Parameters` they send.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path;
+use rustc_serialize::json;
+use Parameters;
+
+/// The command line flags that become message parameters. The po binary fills
+/// this in from its parsed arguments; keeping the mapping here, rather than in
+/// the binary, lets it be used and checked on its own.
+#[derive(Default, Debug, PartialEq, Clone)]
+pub struct Flags {
+    /// The priority, from -2 to 2, if one was given (-p)
+    pub priority: Option<i8>,
+    /// The title (--title)
+    pub title: Option<String>,
+    /// The device to send to (--device)
+    pub device: Option<String>,
+    /// The sound to play (--sound)
+    pub sound: Option<String>,
+    /// Send without a sound, overriding `sound` (--silent)
+    pub silent: bool,
+    /// The URL to call when an emergency notification is acknowledged
+    /// (--callback)
+    pub callback: Option<String>,
+    /// A supplementary URL (--url)
+    pub url: Option<String>,
+    /// A title for the supplementary URL (--url-title)
+    pub url_title: Option<String>,
+    /// Always upload the message and link it (--always-gist)
+    pub always_gist: bool,
+    /// Log the requests made to Pushover (--debug)
+    pub debug: bool
+}
+
+impl Flags {
+    /// Returns the parameters to send a message with these flags. A priority
+    /// that was given is always sent, even 0, Pushover's default.
+    pub fn parameters(self) -> Vec<Parameters> {
+        let mut parameters: Vec<Parameters> = Vec::new();
+
+        if let Some(priority) = self.priority {
+            parameters.push(Parameters::Priority(priority));
+        }
+        if let Some(title) = self.title {
+            parameters.push(Parameters::Title(title));
+        }
+        if let Some(device) = self.device {
+            parameters.push(Parameters::Device(device));
+        }
+        if self.silent {
+            parameters.push(Parameters::Sound("none".to_string()));
+        }
+        else if let Some(sound) = self.sound {
+            parameters.push(Parameters::Sound(sound));
+        }
+        if let Some(url) = self.url {
+            parameters.push(Parameters::URL(url));
+        }
+        if let Some(url_title) = self.url_title {
+            parameters.push(Parameters::URLTitle(url_title));
+        }
+        if let Some(callback) = self.callback {
+            parameters.push(Parameters::Callback(callback));
+        }
+        if self.always_gist {
+            parameters.push(Parameters::Gist);
+        }
+        if self.debug {
+            parameters.push(Parameters::Debug);
+        }
+        parameters
+    }
+}
+
+/// A notification profile read from a JSON file (--params-file), for alerts
+/// sent repeatedly with the same parameters. Every field is optional:
+///
+/// ```json
+/// {"title": "Backup", "priority": 1, "sound": "siren", "device": "phone",
+///  "url": "https://backups.example.com", "url_title": "Dashboard"}
+/// ```
+///
+/// Flags given on the command line take precedence over the profile, and the
+/// profile over the severity rules and defaults in the config file.
+#[derive(RustcDecodable, Default, Debug, PartialEq, Clone)]
+pub struct Profile {
+    pub title: Option<String>,
+    pub priority: Option<i8>,
+    pub sound: Option<String>,
+    pub device: Option<String>,
+    pub url: Option<String>,
+    pub url_title: Option<String>
+}
+
+impl Profile {
+    /// Reads a profile from a JSON file, returning a description of what went
+    /// wrong if it can't be read or parsed.
+    pub fn read(path: &path::Path) -> Result<Profile, String> {
+        let mut buf = String::new();
+        let mut file = try!(File::open(path).map_err(|e| e.to_string()));
+
+        try!(file.read_to_string(&mut buf).map_err(|e| e.to_string()));
+        json::decode(&buf).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Flags;
+    use Parameters::*;
+
+    #[test]
+    fn no_flags() {
+        assert_eq!(Flags::default().parameters(), vec![]);
+    }
+
+    #[test]
+    fn priority_given() {
+        let flags = Flags { priority: Some(1), ..Flags::default() };
+        assert_eq!(flags.parameters(), vec![Priority(1)]);
+    }
+
+    #[test]
+    fn priority_zero_is_sent() {
+        // -p 0 is Pushover's default, but was still given, so it's sent:
+        let flags = Flags { priority: Some(0), ..Flags::default() };
+        assert_eq!(flags.parameters(), vec![Priority(0)]);
+    }
+
+    #[test]
+    fn priority_absent() {
+        let flags = Flags { title: Some("t".to_string()), ..Flags::default() };
+        assert_eq!(flags.parameters(), vec![Title("t".to_string())]);
+    }
+
+    #[test]
+    fn title_and_device() {
+        let flags = Flags {
+            title: Some("Backup".to_string()),
+            device: Some("phone".to_string()),
+            ..Flags::default()
+        };
+        assert_eq!(flags.parameters(),
+                   vec![Title("Backup".to_string()), Device("phone".to_string())]);
+    }
+
+    #[test]
+    fn sound() {
+        let flags = Flags { sound: Some("siren".to_string()), ..Flags::default() };
+        assert_eq!(flags.parameters(), vec![Sound("siren".to_string())]);
+    }
+
+    #[test]
+    fn silent_overrides_sound() {
+        let flags = Flags {
+            sound: Some("siren".to_string()),
+            silent: true,
+            ..Flags::default()
+        };
+        assert_eq!(flags.parameters(), vec![Sound("none".to_string())]);
+    }
+
+    #[test]
+    fn url_and_url_title() {
+        let flags = Flags {
+            url: Some("https://example.com".to_string()),
+            url_title: Some("Dashboard".to_string()),
+            ..Flags::default()
+        };
+        assert_eq!(flags.parameters(),
+                   vec![URL("https://example.com".to_string()),
+                        URLTitle("Dashboard".to_string())]);
+    }
+
+    #[test]
+    fn callback() {
+        let flags = Flags {
+            priority: Some(2),
+            callback: Some("https://example.com/ack".to_string()),
+            ..Flags::default()
+        };
+        assert_eq!(flags.parameters(),
+                   vec![Priority(2), Callback("https://example.com/ack".to_string())]);
+    }
+
+    #[test]
+    fn always_gist() {
+        let flags = Flags { always_gist: true, ..Flags::default() };
+        assert_eq!(flags.parameters(), vec![Gist]);
+    }
+
+    #[test]
+    fn debug() {
+        let flags = Flags { debug: true, ..Flags::default() };
+        assert_eq!(flags.parameters(), vec![Debug]);
+    }
+}
@@ -5,162 +5,2054 @@
 extern crate curl;
 extern crate url;
 extern crate rustc_serialize;
+#[cfg(feature = "config")]
 extern crate regex;
+#[cfg(feature = "encryption")]
+extern crate ring;
+extern crate time;
+extern crate hostname;
+#[macro_use]
+extern crate log;
 
-use std::collections::BTreeMap;
-use curl::http;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher, SipHasher};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
 use url::form_urlencoded;
+use url::percent_encoding::{utf8_percent_encode, USERINFO_ENCODE_SET};
+use rustc_serialize::base64::{self, ToBase64};
 use rustc_serialize::json::{self, ToJson};
 use self::Parameters::*;
+use transport::{CurlTransport, Response, Transport};
 
+pub mod audit;
+#[cfg(feature = "config")]
 pub mod config;
+pub mod dedup;
+pub mod flags;
+pub mod markdown;
+pub mod template;
+pub mod transport;
+pub mod webhook;
 
 /// Optional parameters for Pushover API messages
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Hash, Debug)]
 pub enum Parameters {
     /// A numeric priority from -2 (lowest priority) to 2 (emergency priority)
-    /// (Default: 0)
+    /// (Default: 0). Note that -2 doesn't alert the user at all: the message
+    /// only appears in the Pushover app, and any `Sound` is ignored.
     Priority(i8),
-    /// A title for the push notification
+    /// A title for the push notification, up to 250 characters
     Title(String),
     /// A string identifying the device to send the notification to
     Device(String),
-    /// The notification sound to play (out of the Pushover-supported sounds)
+    /// Several devices to send the notification to, out of the user's
+    /// devices
+    Devices(Vec<String>),
+    /// The notification sound to play (out of the Pushover-supported sounds);
+    /// use "none" for a silent notification
     Sound(String),
     /// A supplementary URL to send with the notification
     URL(String),
     /// A title to give the supplementary URL
     URLTitle(String),
+    /// Render the message body as Pushover's HTML subset, such as <b>, <i>,
+    /// and <a href> (see `markdown::markdown_to_pushover_html`)
+    HTML,
+    /// Tags to categorize the notification with, so that it can later be
+    /// cancelled along with every other notification sharing a tag
+    Tags(Vec<String>),
+    /// A URL that Pushover will POST to when an emergency-priority
+    /// notification is acknowledged; only valid with `Priority(2)`
+    Callback(String),
+    /// How often, in seconds (at least 30), to resend an emergency-priority
+    /// notification until it's acknowledged; required with `Priority(2)`
+    Retry(u32),
+    /// How long, in seconds (at most 10800), to keep resending an
+    /// emergency-priority notification; required with `Priority(2)`
+    Expire(u32),
     /// Gist the full message body and link it as a supplementary URL with
-    /// title "Full Output (GitHub Gist)". This option supersedes the URL and
-    /// URLTitle options if those are also provided.
+    /// title "Full Output (GitHub Gist)", or upload it to the snippet backend
+    /// set in the request Options. The upload is always the complete message
+    /// passed to `push`, never the truncated or split text that's sent. This
+    /// option supersedes the URL and URLTitle options if those are also
+    /// provided.
     Gist,
-    /// Enable debugging output
+    /// An image to attach to the notification, up to `ATTACHMENT_LIMIT` bytes
+    Attachment(Attachment),
+    /// An arbitrary field and value to send as is, for Pushover API features
+    /// the other parameters don't cover yet. It's unchecked: po doesn't
+    /// validate it, and a field the other parameters also send is sent twice.
+    Field(String, String),
+    /// Log the request body through the `log` crate at debug level
     Debug
 }
 
-#[derive(RustcDecodable)]
-struct MessagesJson {
-    status: isize,
-    errors: Vec<String>
+// Render a parameter as the API field it sends, such as "priority=2", for
+// logging.
+impl fmt::Display for Parameters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Priority(p) => write!(f, "priority={}", p),
+            Title(ref t) => write!(f, "title={}", t),
+            Device(ref d) => write!(f, "device={}", d),
+            Devices(ref ds) => write!(f, "device={}", ds.join(",")),
+            Sound(ref s) => write!(f, "sound={}", s),
+            URL(ref u) => write!(f, "url={}", u),
+            URLTitle(ref ut) => write!(f, "url_title={}", ut),
+            HTML => write!(f, "html=1"),
+            Tags(ref t) => write!(f, "tags={}", t.join(",")),
+            Callback(ref c) => write!(f, "callback={}", c),
+            Retry(r) => write!(f, "retry={}", r),
+            Expire(e) => write!(f, "expire={}", e),
+            Gist => write!(f, "gist"),
+            Parameters::Attachment(ref a) => {
+                write!(f, "attachment_type={} ({} bytes)", a.mime_type, a.bytes.len())
+            },
+            Field(ref k, ref v) => write!(f, "{}={}", k, v),
+            Debug => write!(f, "debug")
+        }
+    }
+}
+
+/// An image attached to a notification (see `Parameters::Attachment`)
+#[derive(PartialEq, Clone, Hash, Debug)]
+pub struct Attachment {
+    /// The image file's contents
+    pub bytes: Vec<u8>,
+    /// The image's MIME type, such as "image/png"
+    pub mime_type: String
+}
+
+impl Attachment {
+    /// Creates an Attachment from an image's contents and MIME type.
+    pub fn new(bytes: Vec<u8>, mime_type: &str) -> Attachment {
+        Attachment {
+            bytes: bytes,
+            mime_type: mime_type.to_string()
+        }
+    }
+
+    /// Downloads an image to attach, such as a chart served by a monitoring
+    /// system, taking its MIME type from the response's Content-Type.
+    /// Pushover needs the image itself rather than a link to it.
+    pub fn from_url(url: &str) -> Result<Attachment, Vec<String>> {
+        Attachment::from_url_with_options(url, &Options::default())
+    }
+
+    /// Like `from_url`, but with the given request Options.
+    pub fn from_url_with_options(url: &str,
+                                 options: &Options) -> Result<Attachment, Vec<String>> {
+        // Downloads don't need Pushover credentials:
+        Client::with_options("", "", options.clone()).fetch_attachment(url)
+    }
+}
+
+/// Services that long message bodies can be uploaded to and linked from a
+/// notification (see `Parameters::Gist`)
+#[derive(PartialEq, Clone)]
+pub enum SnippetBackend {
+    /// An anonymous GitHub Gist
+    Gist,
+    /// A private snippet on a GitLab instance, such as
+    /// "https://gitlab.com", created with a personal access token
+    GitLabSnippet {
+        base_url: String,
+        token: String
+    },
+    /// An anonymous paste service, such as "https://0x0.st", that accepts a
+    /// multipart form upload of a `file` field and responds with the paste's
+    /// URL as plain text
+    Paste {
+        url: String
+    }
+}
+
+/// What `push` does with a message body too long for Pushover (over
+/// `Options::max_message_len` characters)
+#[derive(PartialEq, Clone, Debug)]
+pub enum Overflow {
+    /// Cut the message down to the maximum length
+    Truncate,
+    /// Refuse to send the message, returning an error, unless the full
+    /// message is being uploaded with the `Gist` parameter
+    Error,
+    /// Split the message into several notifications sent in order, each
+    /// titled with its part number, such as "(1/3) po"
+    Split
+}
+
+/// What `push` does when Pushover rejects a message's sound as invalid, such
+/// as a sound name left stale by a change to Pushover's sound list
+#[derive(PartialEq, Clone, Debug)]
+pub enum InvalidSound {
+    /// Fail to send the message, returning Pushover's error
+    Error,
+    /// Resend the message once without a sound, so the user's default plays
+    Drop,
+    /// Resend the message once with the given sound instead
+    Fallback(String)
+}
+
+/// Options controlling where and how API requests are made
+#[derive(Clone)]
+pub struct Options {
+    /// The base URL of the Pushover API, without a trailing slash; point this
+    /// at a mock server or proxy to redirect requests
+    /// (Default: "https://api.pushover.net/1")
+    pub pushover_url: String,
+    /// The base URL of the GitHub API used for Gist uploads, without a
+    /// trailing slash (Default: "https://api.github.com")
+    pub github_url: String,
+    /// Where the `Gist` parameter uploads message bodies to
+    /// (Default: SnippetBackend::Gist)
+    pub snippet_backend: SnippetBackend,
+    /// A GitHub personal access token to create Gists with, so that they can
+    /// later be deleted with `delete_gist`; Gists are created anonymously
+    /// without one (Default: None)
+    pub github_token: Option<String>,
+    /// An extension, such as ".log" or ".rs", to give the file name of
+    /// uploaded Gists so that GitHub highlights them (Default: "")
+    pub gist_extension: String,
+    /// The longest message, in characters, that Pushover accepts for this
+    /// account (Default: MESSAGE_LIMIT, 1024)
+    pub max_message_len: usize,
+    /// What to do with messages too long to send (Default: Overflow::Truncate)
+    pub overflow: Overflow,
+    /// What to do when a message's sound is rejected as invalid
+    /// (Default: InvalidSound::Error)
+    pub invalid_sound: InvalidSound,
+    /// How many times to resend a push that failed with a retryable error
+    /// (see `is_retryable`), and to retry a `Gist` parameter's upload that
+    /// failed with one (see `SnippetError::is_retryable`) (Default: 0)
+    pub retries: u32,
+    /// How long to wait before the first resend, in seconds, doubling before
+    /// each one after it (Default: 1)
+    pub retry_delay: u64,
+    /// Wait a random time between 0 and the backoff delay before each resend
+    /// instead of the whole delay ("full jitter"), so that many clients
+    /// failing at once don't all retry at once. Rate limits still wait the
+    /// whole time Pushover asks for. (Default: false)
+    pub retry_jitter: bool,
+    /// Extra HTTP headers to send with every request to Pushover and the
+    /// snippet backend, such as an auth token or tracing ID for a gateway in
+    /// front of them, but not with attachment downloads. These can't override
+    /// the Content-Type po sends. (Default: none)
+    pub headers: Vec<(String, String)>,
+    /// Send empty or whitespace-only messages as a single space, rather than
+    /// returning an error (Default: false)
+    pub allow_empty: bool,
+    /// Fail the whole push if the `Gist` parameter's upload fails, rather
+    /// than sending the notification without a link to the full message
+    /// (Default: false)
+    pub gist_required: bool,
+    /// Disable TLS certificate verification for every request. This is
+    /// unsafe, and only meant for controlled environments behind a
+    /// TLS-intercepting proxy (Default: false)
+    pub insecure: bool
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            pushover_url: "https://api.pushover.net/1".to_string(),
+            github_url: "https://api.github.com".to_string(),
+            snippet_backend: SnippetBackend::Gist,
+            github_token: None,
+            gist_extension: String::new(),
+            max_message_len: MESSAGE_LIMIT,
+            overflow: Overflow::Truncate,
+            invalid_sound: InvalidSound::Error,
+            retries: 0,
+            retry_delay: 1,
+            retry_jitter: false,
+            headers: Vec::new(),
+            allow_empty: false,
+            gist_required: false,
+            insecure: false
+        }
+    }
+}
+
+#[derive(RustcDecodable)]
+struct MessagesJson {
+    status: isize,
+    errors: Option<Vec<String>>
+}
+
+#[derive(RustcDecodable)]
+struct SentJson {
+    request: String,
+    receipt: Option<String>
+}
+
+/// What happened to a message that was sent successfully
+#[derive(PartialEq, Clone, Debug)]
+pub struct SendOutcome {
+    /// The ID Pushover gave the request
+    pub request_id: String,
+    /// Whether the message was cut down to fit Pushover's maximum length
+    pub truncated: bool,
+    /// The link to the full message, if it was uploaded with the `Gist`
+    /// parameter
+    pub gist_url: Option<String>,
+    /// The receipt for an emergency-priority message, for looking up its
+    /// status (see `receipt_status`)
+    pub receipt: Option<String>,
+    /// The priority the message was sent with (0 if none was given)
+    pub priority: i8
+}
+
+impl SendOutcome {
+    /// Returns what sending the message amounted to at its priority (see
+    /// `SendResult`).
+    pub fn result(&self) -> SendResult {
+        match self.receipt {
+            Some(ref receipt) => SendResult::Emergency {
+                request_id: self.request_id.clone(),
+                receipt: receipt.clone()
+            },
+            None if self.priority == -2 => SendResult::Silent {
+                request_id: self.request_id.clone()
+            },
+            None => SendResult::Delivered { request_id: self.request_id.clone() }
+        }
+    }
+}
+
+/// What a successful send means, which depends on the message's priority
+#[derive(PartialEq, Clone, Debug)]
+pub enum SendResult {
+    /// The notification was delivered to the user's devices
+    Delivered { request_id: String },
+    /// An emergency-priority notification was delivered and repeats until
+    /// acknowledged; its receipt looks up its status (see `receipt_status`)
+    Emergency { request_id: String, receipt: String },
+    /// A lowest-priority (-2) message was accepted, but only appears in the
+    /// Pushover app, without a notification
+    Silent { request_id: String }
+}
+
+/// An update to the fields of a user's Glances widgets, such as a watch
+/// complication; fields left as None are unchanged. Pushover shows at most
+/// 100 characters of each text field.
+#[derive(Default, PartialEq, Clone, Debug)]
+pub struct Glance {
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub subtext: Option<String>,
+    /// A number, which must fit in 32 bits
+    pub count: Option<i64>,
+    /// A percentage for a progress bar or gauge, from 0 to 100
+    pub percent: Option<u8>
+}
+
+/// Which notifications `cancel_all` cancelled, and which it couldn't
+#[derive(PartialEq, Clone, Debug)]
+pub struct CancelSummary {
+    /// The receipts of the notifications that were cancelled
+    pub cancelled: Vec<String>,
+    /// The receipts that couldn't be cancelled, each with its errors
+    pub failed: Vec<(String, Vec<String>)>
+}
+
+/// How an emergency-priority notification waited on with `send_and_wait_ack`
+/// was resolved
+#[derive(PartialEq, Clone, Debug)]
+pub struct AckResult {
+    /// Whether the notification was acknowledged before it expired
+    pub acknowledged: bool,
+    /// The user key of the user who acknowledged the notification (or "")
+    pub acknowledged_by: String,
+    /// The name of the device the notification was acknowledged on (or "")
+    pub acknowledged_by_device: String,
+    /// When the notification was acknowledged, as a Unix timestamp (or 0)
+    pub acknowledged_at: i64
+}
+
+#[derive(RustcDecodable)]
+struct LimitsJson {
+    limit: u32,
+    remaining: u32,
+    reset: i64
+}
+
+/// An application's monthly message allowance
+#[derive(PartialEq, Clone, Debug)]
+pub struct RateLimit {
+    /// How many messages the application can send each month
+    pub limit: u32,
+    /// How many messages the application has left this month
+    pub remaining: u32,
+    /// When the allowance resets, as a Unix timestamp
+    pub reset: i64
+}
+
+#[derive(RustcDecodable)]
+struct ValidateJson {
+    devices: Vec<String>
+}
+
+#[derive(RustcDecodable)]
+struct SubscriptionJson {
+    subscribed_user_key: String
+}
+
+#[derive(RustcDecodable)]
+struct ReceiptJson {
+    acknowledged: isize,
+    acknowledged_at: i64,
+    acknowledged_by: String,
+    acknowledged_by_device: String,
+    last_delivered_at: i64,
+    expired: isize,
+    expires_at: i64,
+    called_back: isize,
+    called_back_at: i64
+}
+
+/// The status of an emergency-priority notification's receipt
+#[derive(PartialEq, Clone, Debug)]
+pub struct ReceiptStatus {
+    /// Whether the notification has been acknowledged
+    pub acknowledged: bool,
+    /// When the notification was acknowledged, as a Unix timestamp (or 0)
+    pub acknowledged_at: i64,
+    /// The user key of the user who acknowledged the notification (or "")
+    pub acknowledged_by: String,
+    /// The name of the device the notification was acknowledged on (or "")
+    pub acknowledged_by_device: String,
+    /// When the notification was last delivered, as a Unix timestamp
+    pub last_delivered_at: i64,
+    /// Whether the notification has stopped being retried
+    pub expired: bool,
+    /// When the notification stops (or stopped) being retried, as a Unix
+    /// timestamp
+    pub expires_at: i64,
+    /// Whether Pushover has called the notification's callback URL
+    pub called_back: bool,
+    /// When the callback URL was called, as a Unix timestamp (or 0)
+    pub called_back_at: i64
+}
+
+/// What the descriptions of Gists uploaded by po start with, so that
+/// `Client::po_gists` can tell them apart from the user's own
+pub const GIST_MARKER: &'static str = "[po] ";
+
+#[derive(RustcEncodable)]
+struct GistPost {
+    description: String,
+    files: BTreeMap<String, json::Json>
+}
+
+#[derive(RustcDecodable)]
+struct GistFileResponse {
+    raw_url: String
+}
+
+#[derive(RustcDecodable)]
+struct GistResponse {
+    id: String,
+    html_url: String,
+    files: BTreeMap<String, GistFileResponse>
+}
+
+#[derive(RustcDecodable)]
+struct GistListJson {
+    id: String,
+    html_url: String,
+    description: Option<String>,
+    created_at: String
+}
+
+/// A Gist uploaded by po, as listed by `Client::po_gists`
+#[derive(PartialEq, Clone, Debug)]
+pub struct GistSummary {
+    /// The Gist's ID
+    pub id: String,
+    /// The Gist's web page
+    pub html_url: String,
+    /// When the Gist was created, as a Unix timestamp
+    pub created_at: i64
+}
+
+// The URL of the next page of results from a GitHub Link header, if any.
+fn next_page(link: &str) -> Option<String> {
+    link.split(',')
+        .find(|part| part.contains("rel=\"next\""))
+        .and_then(|part| match (part.find('<'), part.find('>')) {
+            (Some(start), Some(end)) if start < end => Some(part[start + 1..end].to_string()),
+            _ => None
+        })
+}
+
+/// A successfully created Gist
+#[derive(PartialEq, Clone, Debug)]
+pub struct GistResult {
+    /// The Gist's web page
+    pub html_url: String,
+    /// The URL of the uploaded file's raw contents
+    pub raw_url: String,
+    /// The Gist's ID
+    pub id: String
+}
+
+/// Errors from uploading a message body to a snippet backend
+#[derive(PartialEq, Clone, Debug)]
+pub enum SnippetError {
+    /// The upload request couldn't be encoded
+    Encoding,
+    /// The request couldn't be made, with the transport's description of why
+    Transport(String),
+    /// The service responded with an unsuccessful HTTP status code
+    Status(u32),
+    /// GitHub's rate limit for the upload was exceeded; it resets at the given
+    /// Unix timestamp
+    RateLimited(i64),
+    /// The service's response couldn't be understood
+    MalformedResponse
+}
+
+impl fmt::Display for SnippetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnippetError::Encoding => write!(f, "couldn't encode the upload"),
+            SnippetError::Transport(ref e) => write!(f, "{}{}", TRANSPORT_ERROR, e),
+            SnippetError::Status(n) => write!(f, "upload failed with HTTP status {}", n),
+            SnippetError::RateLimited(reset) => {
+                let at = time::at(time::Timespec::new(reset, 0));
+                match time::strftime("%Y-%m-%d %H:%M:%S", &at) {
+                    Ok(t) => write!(f, "GitHub rate limit exceeded, resets at {}", t),
+                    Err(_) => write!(f, "GitHub rate limit exceeded")
+                }
+            },
+            SnippetError::MalformedResponse => write!(f, "malformed upload response")
+        }
+    }
+}
+
+impl SnippetError {
+    /// Returns true if the upload might succeed if retried: the request
+    /// couldn't be made, or the service had a server-side (5xx) error. Rate
+    /// limits aren't retryable, as GitHub's can last up to an hour.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            SnippetError::Transport(_) => true,
+            SnippetError::Status(n) => n >= 500,
+            _ => false
+        }
+    }
+}
+
+#[derive(RustcEncodable)]
+struct GitLabSnippetPost {
+    title: String,
+    file_name: String,
+    content: String,
+    visibility: String
+}
+
+#[derive(RustcDecodable)]
+struct GitLabSnippetResponse {
+    web_url: String
+}
+
+/// The maximum length of a message body, in characters, on standard Pushover
+/// plans; longer messages are truncated or refused (see `Overflow`). Plans that
+/// allow longer messages can raise the limit with `Options::max_message_len`.
+pub const MESSAGE_LIMIT: usize = 1024;
+
+/// The longest title Pushover accepts, in characters
+pub const TITLE_LIMIT: usize = 250;
+
+/// The largest attachment Pushover accepts, in bytes
+pub const ATTACHMENT_LIMIT: usize = 5242880;
+
+/// The meaning of each message priority
+pub const PRIORITIES: &'static [(i8, &'static str)] = &[
+    (-2, "lowest (--silent-log): no notification or sound, only shown in the Pushover app"),
+    (-1, "low: a quiet notification, without sound or vibration"),
+    (0, "normal: a notification with the user's sound and vibration"),
+    (1, "high: bypasses the user's quiet hours, highlighted in red"),
+    (2, "emergency: repeats until acknowledged by the user")
+];
+
+/// The sounds known to Pushover, with their descriptions
+pub const SOUNDS: &'static [(&'static str, &'static str)] = &[
+    ("pushover", "Pushover (default)"),
+    ("bike", "Bike"),
+    ("bugle", "Bugle"),
+    ("cashregister", "Cash Register"),
+    ("classical", "Classical"),
+    ("cosmic", "Cosmic"),
+    ("falling", "Falling"),
+    ("gamelan", "Gamelan"),
+    ("incoming", "Incoming"),
+    ("intermission", "Intermission"),
+    ("magic", "Magic"),
+    ("mechanical", "Mechanical"),
+    ("pianobar", "Piano Bar"),
+    ("siren", "Siren"),
+    ("spacealarm", "Space Alarm"),
+    ("tugboat", "Tug Boat"),
+    ("alien", "Alien Alarm (long)"),
+    ("climb", "Climb (long)"),
+    ("persistent", "Persistent (long)"),
+    ("echo", "Pushover Echo (long)"),
+    ("updown", "Up Down (long)"),
+    ("vibrate", "Vibrate Only"),
+    ("none", "None (silent)")
+];
+
+/// The prefix given to errors caused by a network or transport failure, as
+/// opposed to the Pushover API rejecting a request.
+pub const TRANSPORT_ERROR: &'static str = "transport error: ";
+
+/// The prefix given to the error returned when Pushover rate limits a request
+/// (HTTP 429); see `retry_after`.
+pub const RATE_LIMITED: &'static str = "rate limited";
+
+/// Returns true if the errors returned by a failed request indicate that it
+/// might succeed if retried: a transport failure, a server-side (5xx) error,
+/// or rate limiting. Errors from the API rejecting the request itself are not
+/// retryable.
+pub fn is_retryable(errors: &[String]) -> bool {
+    errors.iter().any(|e| {
+        e.starts_with(TRANSPORT_ERROR) || e.starts_with("API error 5") ||
+            e.starts_with(RATE_LIMITED)
+    })
+}
+
+/// Returns how many seconds Pushover asked to wait before retrying, if the
+/// errors returned by a failed request include a rate limit error with a
+/// Retry-After time.
+pub fn retry_after(errors: &[String]) -> Option<u64> {
+    errors.iter()
+          .filter(|e| e.starts_with(RATE_LIMITED))
+          .filter_map(|e| e.split("retry after ").nth(1))
+          .filter_map(|secs| secs.trim_right_matches('s').parse::<u64>().ok())
+          .next()
+}
+
+// Returns the errors from the body of a failed Pushover API response. Only
+// call this for a response already known to have failed; when the body
+// doesn't list any errors (or isn't JSON), a general error is returned.
+fn api_error(response_body: &str) -> Vec<String> {
+    match json::decode::<MessagesJson>(response_body) {
+        Ok(MessagesJson { errors: Some(ref errors), .. }) if !errors.is_empty() => {
+            errors.clone()
+        },
+        _ => vec![format!("general API error")]
+    }
+}
+
+// A stable hash identifying a push request by its user key, message body, and
+// parameters.
+fn request_key(user: &str, message: &str, parameters: &[Parameters]) -> u64 {
+    let mut hasher = SipHasher::new();
+
+    user.hash(&mut hasher);
+    message.hash(&mut hasher);
+    parameters.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Like `request_key`, but identifying one part of a split message by the
+// whole message, the caller's parameters, and the part's index, so that a
+// part keeps its key even if its title or link change between attempts.
+fn part_key(user: &str, message: &str, parameters: &[Parameters], part: usize) -> u64 {
+    let mut hasher = SipHasher::new();
+
+    user.hash(&mut hasher);
+    message.hash(&mut hasher);
+    parameters.hash(&mut hasher);
+    part.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Encode the fields of a Pushover API request as an
+// application/x-www-form-urlencoded body. Every byte outside of the unreserved
+// set is percent-encoded, so message bodies containing newlines, tabs, `&`,
+// `=`, `%`, and multi-byte UTF-8 all reach Pushover intact; spaces become `+`.
+fn form_body(fields: Vec<(String, String)>) -> String {
+    form_urlencoded::serialize(fields.into_iter())
+}
+
+// Lay out the fields of a Pushover API request as a table of names and
+// values before encoding, for debug logging. Values too long to read at a
+// glance, such as attachments, are cut short.
+fn field_table(fields: &[(String, String)]) -> String {
+    let width = fields.iter().map(|&(ref name, _)| name.len()).max().unwrap_or(0);
+    let mut table = String::new();
+
+    for &(ref name, ref value) in fields.iter() {
+        let length = value.chars().count();
+        let shown = if length > 80 {
+            let start: String = value.chars().take(60).collect();
+            format!("{:?}... ({} characters)", start, length)
+        }
+        else {
+            format!("{:?}", value)
+        };
+        table.push_str(format!("  {:<width$}  {}\n", name, shown, width = width).as_ref());
+    }
+    table
+}
+
+// Map the outcome of a Pushover API request to the crate's error convention,
+// returning the response body on success.
+fn api_response(result: Result<Response, String>) -> Result<String, Vec<String>> {
+    match result {
+        Ok(res) => {
+            // A proxy in front of the API may answer with a page that isn't
+            // UTF-8; that only matters for the responses whose body is read:
+            let body = std::str::from_utf8(&res.body).ok();
+            match (res.code, body) {
+                // Only trust a 200 whose body reports success:
+                (200, Some(body)) => match json::decode::<MessagesJson>(body) {
+                    Ok(ref response) if response.status == 1 => Ok(body.to_string()),
+                    Ok(_) => Err(api_error(body)),
+                    Err(_) => Err(vec![format!("malformed API response")])
+                },
+                // A 429's body may not be JSON, but its Retry-After header
+                // says when to try again:
+                (429, _) => {
+                    match res.header("retry-after")
+                             .and_then(|r| r.trim().parse::<u64>().ok()) {
+                        Some(secs) => Err(vec![format!("{}, retry after {}s",
+                                                       RATE_LIMITED, secs)]),
+                        None => Err(vec![RATE_LIMITED.to_string()])
+                    }
+                },
+                (400...499, Some(body)) => Err(api_error(body)),
+                (200, None) | (400...499, None) => {
+                    Err(vec![format!("malformed API response")])
+                },
+                (n, _) => Err(vec![format!("API error {}", n)])
+            }
+        },
+        Err(e) => Err(vec![format!("{}{}", TRANSPORT_ERROR, e)])
+    }
+}
+
+// Map the outcome of a snippet upload request to a SnippetError, returning the
+// response body on success.
+fn snippet_response(result: Result<Response, String>) -> Result<String, SnippetError> {
+    match result {
+        Ok(res) => {
+            match res.code {
+                200 | 201 | 204 => match std::str::from_utf8(&res.body) {
+                    Ok(body) => Ok(body.to_string()),
+                    Err(_) => Err(SnippetError::MalformedResponse)
+                },
+                // GitHub throttles anonymous Gists with a 403 and the time
+                // the limit resets at:
+                403 if res.header("x-ratelimit-remaining")
+                          .map_or(false, |r| r.trim() == "0") => {
+                    match res.header("x-ratelimit-reset")
+                             .and_then(|r| r.trim().parse::<i64>().ok()) {
+                        Some(reset) => Err(SnippetError::RateLimited(reset)),
+                        None => Err(SnippetError::Status(403))
+                    }
+                },
+                n => Err(SnippetError::Status(n))
+            }
+        },
+        Err(e) => Err(SnippetError::Transport(e))
+    }
+}
+
+/// A reusable client for the Pushover API and the snippet backends, holding an
+/// API token and user key. A Client keeps its transport, and so its open
+/// connections, between requests; use one for sending many notifications
+/// rather than the free functions, which each make a new Client.
+pub struct Client {
+    token: String,
+    user: String,
+    transport: Box<Transport>,
+    options: Options,
+    // The outcomes of the requests delivered during the current send, by
+    // `request_key`, so that retrying a send never delivers a request twice
+    delivered: HashMap<u64, SendOutcome>,
+    // The links to the full texts uploaded during the current send, by
+    // message and title, so that retrying a send doesn't upload them again
+    uploaded: HashMap<(String, String), (String, String)>,
+    // The state of the generator for retry jitter (see `random`)
+    rng: u64
+}
+
+impl Client {
+    /// Creates a Client that sends with the given API token and user key.
+    pub fn new(token: &str, user: &str) -> Client {
+        Client::with_options(token, user, Options::default())
+    }
+
+    /// Like `new`, but making requests with the given Options.
+    pub fn with_options(token: &str, user: &str, options: Options) -> Client {
+        let transport = CurlTransport::new(options.insecure);
+        Client::with_transport(token, user, options, Box::new(transport))
+    }
+
+    /// Like `with_options`, but making requests through the given Transport
+    /// instead of curl, such as a `transport::MockTransport` in tests.
+    pub fn with_transport(token: &str, user: &str, options: Options,
+                          transport: Box<Transport>) -> Client {
+        Client {
+            token: token.to_string(),
+            user: user.to_string(),
+            transport: transport,
+            options: options,
+            delivered: HashMap::new(),
+            uploaded: HashMap::new(),
+            // Seed from the clock, so that Clients retrying at the same
+            // moment on different machines don't pick the same delays; the
+            // generator's state must never be 0:
+            rng: time::precise_time_ns() | 1
+        }
+    }
+
+    /// Creates a Client from the API token and user key in the PUSHOVER_TOKEN
+    /// and PUSHOVER_USER environment variables, such as for a container
+    /// configured through its environment. Returns an error naming each
+    /// variable that isn't set.
+    pub fn from_env() -> Result<Client, Vec<String>> {
+        let token = std::env::var("PUSHOVER_TOKEN");
+        let user = std::env::var("PUSHOVER_USER");
+
+        match (token, user) {
+            (Ok(token), Ok(user)) => Ok(Client::new(token.as_ref(), user.as_ref())),
+            (token, user) => {
+                let mut errors = Vec::new();
+                if token.is_err() {
+                    errors.push(format!("PUSHOVER_TOKEN is not set"));
+                }
+                if user.is_err() {
+                    errors.push(format!("PUSHOVER_USER is not set"));
+                }
+                Err(errors)
+            }
+        }
+    }
+
+    /// Creates a Client from the API token and user key in a po config file
+    /// (see `config::read`).
+    #[cfg(feature = "config")]
+    pub fn from_config(path: &std::path::Path) -> Result<Client, config::ReadError> {
+        let (token, user) = try!(config::read(path));
+        Ok(Client::new(token.as_ref(), user.as_ref()))
+    }
+
+    /// Returns the Options this Client makes requests with.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    // The headers to send with a request: its own, then the custom headers
+    // from our Options, skipping any that would override its Content-Type.
+    fn headers(&self, own: &[(&str, &str)]) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> =
+            own.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect();
+        for &(ref name, ref value) in self.options.headers.iter() {
+            if name.to_lowercase() != "content-type" {
+                headers.push((name.clone(), value.clone()));
+            }
+        }
+        headers
+    }
+
+    // POST an already-urlencoded form body to a Pushover API endpoint,
+    // returning the response body.
+    fn api_post(&mut self, url: &str, body: &str) -> Result<String, Vec<String>> {
+        let headers = self.headers(&[("Content-Type", "application/x-www-form-urlencoded")]);
+        api_response(self.transport.post(url, body, &headers))
+    }
+
+    // GET a Pushover API endpoint, returning the response body.
+    fn api_get(&mut self, url: &str) -> Result<String, Vec<String>> {
+        let headers = self.headers(&[]);
+        api_response(self.transport.get(url, &headers))
+    }
+
+    /// Download an image to attach to a notification (see
+    /// `Attachment::from_url`).
+    pub fn fetch_attachment(&mut self, url: &str) -> Result<Attachment, Vec<String>> {
+        // The image may be hosted anywhere, so don't send it the custom
+        // headers, which can carry credentials for Pushover's gateway:
+        let res = match self.transport.get(url, &[]) {
+            Ok(res) => res,
+            Err(e) => return Err(vec![format!("{}{}", TRANSPORT_ERROR, e)])
+        };
+
+        match res.code {
+            200 => {},
+            n => return Err(vec![format!("attachment download failed with HTTP {}", n)])
+        }
+        let bytes = res.body.clone();
+        if bytes.len() > ATTACHMENT_LIMIT {
+            return Err(vec![format!("attachment exceeds {} bytes", ATTACHMENT_LIMIT)]);
+        }
+        let mime_type = match res.header("content-type") {
+            Some(t) => t.split(';').next().unwrap_or("").trim().to_string(),
+            None => String::new()
+        };
+        if !mime_type.starts_with("image/") {
+            return Err(vec![format!("attachment is not an image ({})", mime_type)]);
+        }
+        Ok(Attachment {
+            bytes: bytes,
+            mime_type: mime_type
+        })
+    }
+
+    /// Post a message body with a given title to GitHub Gist and return the
+    /// Gist's URLs and ID (see `gist`).
+    pub fn gist(&mut self, message: &str, title: String) -> Result<GistResult, SnippetError> {
+        let mut content = BTreeMap::new();
+        content.insert("content".to_string(), message.to_json());
+        let mut gist_file = BTreeMap::new();
+        let extension = self.options.gist_extension.clone();
+        let file_name = if extension == "" || extension.starts_with(".") {
+            format!("{}{}", title, extension)
+        }
+        else {
+            format!("{}.{}", title, extension)
+        };
+        gist_file.insert(file_name, content.to_json());
+        let gist = GistPost {
+            description: format!("{}{}", GIST_MARKER, title),
+            files: gist_file
+        };
+
+        let json = match json::encode(&gist) {
+            Ok(json) => json,
+            Err(_) => return Err(SnippetError::Encoding)
+        };
+        let json_ref: &str = json.as_ref();
+        let url = format!("{}/gists", self.options.github_url);
+        let url_ref: &str = url.as_ref();
+        let mut headers = self.headers(&[("Content-Type", "application/json"),
+                                         ("User-Agent", "po")]);
+        if let Some(ref token) = self.options.github_token {
+            headers.push(("Authorization".to_string(), format!("token {}", token)));
+        }
+        let body = try!(snippet_response(self.transport.post(url_ref, json_ref, &headers)));
+        let body_ref: &str = body.as_ref();
+
+        match json::decode::<GistResponse>(body_ref) {
+            Ok(response) => {
+                match response.files.values().next() {
+                    Some(file) => Ok(GistResult {
+                        html_url: response.html_url.clone(),
+                        raw_url: file.raw_url.clone(),
+                        id: response.id.clone()
+                    }),
+                    None => Err(SnippetError::MalformedResponse)
+                }
+            },
+            Err(_) => Err(SnippetError::MalformedResponse)
+        }
+    }
+
+    /// Delete a Gist created with a GitHub token, given its ID (see
+    /// `delete_gist`).
+    pub fn delete_gist(&mut self, id: &str, github_token: &str) -> Result<(), SnippetError> {
+        let url = format!("{}/gists/{}", self.options.github_url,
+                          utf8_percent_encode(id, USERINFO_ENCODE_SET));
+        let url_ref: &str = url.as_ref();
+        let auth = format!("token {}", github_token);
+        let auth_ref: &str = auth.as_ref();
+        let headers = self.headers(&[("Authorization", auth_ref), ("User-Agent", "po")]);
+        snippet_response(self.transport.delete(url_ref, &headers)).map(|_| ())
+    }
+
+    /// List the Gists uploaded by po (see `GIST_MARKER`) belonging to the
+    /// owner of a GitHub personal access token, following every page of
+    /// GitHub's results. Gists uploaded without a token can't be listed.
+    pub fn po_gists(&mut self, github_token: &str) -> Result<Vec<GistSummary>, SnippetError> {
+        let auth = format!("token {}", github_token);
+        let auth_ref: &str = auth.as_ref();
+        let headers = self.headers(&[("Authorization", auth_ref), ("User-Agent", "po")]);
+        let mut url = Some(format!("{}/gists?per_page=100", self.options.github_url));
+        let mut gists = Vec::new();
+
+        while let Some(page) = url {
+            let page_ref: &str = page.as_ref();
+            let result = self.transport.get(page_ref, &headers);
+            url = match result {
+                Ok(ref response) => response.header("link").and_then(next_page),
+                Err(_) => None
+            };
+            let body = try!(snippet_response(result));
+            let body_ref: &str = body.as_ref();
+            let listed = try!(json::decode::<Vec<GistListJson>>(body_ref)
+                                  .map_err(|_| SnippetError::MalformedResponse));
+
+            for gist in listed.into_iter() {
+                let from_po = gist.description.as_ref()
+                                  .map_or(false, |d| d.starts_with(GIST_MARKER));
+                if !from_po {
+                    continue;
+                }
+                let created = try!(time::strptime(gist.created_at.as_ref(), "%Y-%m-%dT%H:%M:%SZ")
+                                       .map_err(|_| SnippetError::MalformedResponse));
+                gists.push(GistSummary {
+                    id: gist.id,
+                    html_url: gist.html_url,
+                    created_at: created.to_timespec().sec
+                });
+            }
+        }
+        Ok(gists)
+    }
+
+    /// Delete the Gists uploaded by po (see `po_gists`) more than `max_age`
+    /// seconds ago, returning their IDs.
+    pub fn cleanup_gists(&mut self, github_token: &str,
+                         max_age: i64) -> Result<Vec<String>, SnippetError> {
+        let cutoff = time::get_time().sec - max_age;
+        let mut deleted = Vec::new();
+
+        for gist in try!(self.po_gists(github_token)).into_iter() {
+            if gist.created_at < cutoff {
+                try!(self.delete_gist(gist.id.as_ref(), github_token));
+                deleted.push(gist.id);
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Post a message body with a given title as a private snippet on a
+    /// GitLab instance and return the snippet's URL (see `gitlab_snippet`).
+    pub fn gitlab_snippet(&mut self, message: &str, title: String, base_url: &str,
+                          token: &str) -> Result<String, SnippetError> {
+        let snippet = GitLabSnippetPost {
+            title: title.clone(),
+            file_name: title,
+            content: message.to_string(),
+            visibility: "private".to_string()
+        };
+
+        let json = match json::encode(&snippet) {
+            Ok(json) => json,
+            Err(_) => return Err(SnippetError::Encoding)
+        };
+        let json_ref: &str = json.as_ref();
+        let url = format!("{}/api/v4/snippets", base_url);
+        let url_ref: &str = url.as_ref();
+        let headers = self.headers(&[("Content-Type", "application/json"),
+                                     ("PRIVATE-TOKEN", token),
+                                     ("User-Agent", "po")]);
+        let body = try!(snippet_response(self.transport.post(url_ref, json_ref, &headers)));
+        let body_ref: &str = body.as_ref();
+
+        match json::decode::<GitLabSnippetResponse>(body_ref) {
+            Ok(response) => Ok(response.web_url),
+            Err(_) => Err(SnippetError::MalformedResponse)
+        }
+    }
+
+    /// Post a message body with a given title to an anonymous paste service
+    /// and return the paste's URL (see `paste`).
+    pub fn paste(&mut self, message: &str, title: String,
+                 url: &str) -> Result<String, SnippetError> {
+        let boundary = "------------------------po-paste-boundary";
+        let body = format!("--{}\r\n\
+                            Content-Disposition: form-data; name=\"file\"; \
+                            filename=\"{}.txt\"\r\n\
+                            Content-Type: text/plain; charset=utf-8\r\n\r\n\
+                            {}\r\n--{}--\r\n",
+                           boundary, title.replace("\"", ""), message, boundary);
+        let body_ref: &str = body.as_ref();
+        let content_type = format!("multipart/form-data; boundary={}", boundary);
+        let content_type_ref: &str = content_type.as_ref();
+
+        let headers = self.headers(&[("Content-Type", content_type_ref),
+                                     ("User-Agent", "po")]);
+        let response = try!(snippet_response(self.transport.post(url, body_ref, &headers)));
+        let paste_url = response.trim();
+
+        if paste_url == "" {
+            return Err(SnippetError::MalformedResponse);
+        }
+        Ok(paste_url.to_string())
+    }
+
+    /// Upload a message body with a given title to this Client's snippet
+    /// backend, returning the link to it and a title for that link.
+    pub fn upload_snippet(&mut self, message: &str,
+                          title: String) -> Result<(String, String), SnippetError> {
+        match self.options.snippet_backend.clone() {
+            SnippetBackend::Gist => {
+                self.gist(message, title)
+                    .map(|g| (g.html_url, "Full Output (GitHub Gist)".to_string()))
+            },
+            SnippetBackend::GitLabSnippet { base_url, token } => {
+                self.gitlab_snippet(message, title, base_url.as_ref(), token.as_ref())
+                    .map(|url| (url, "Full Output (GitLab Snippet)".to_string()))
+            },
+            SnippetBackend::Paste { url } => {
+                self.paste(message, title, url.as_ref())
+                    .map(|url| (url, "Full Output (Paste)".to_string()))
+            }
+        }
+    }
+
+    /// Like `upload_snippet`, but retries an upload that failed with a
+    /// retryable error (see `SnippetError::is_retryable`) as many times as
+    /// `Options::retries` allows, as the `Gist` parameter does.
+    pub fn upload_snippet_retrying(&mut self, message: &str,
+                                   title: String) -> Result<(String, String), SnippetError> {
+        self.backoff("upload", |client| client.upload_snippet(message, title.clone()),
+                     |e| if e.is_retryable() { Some(None) } else { None })
+    }
+
+    /// Pushes a message to this Client's user, with a message body and array
+    /// of optional Parameters (see `push`).
+    pub fn send(&mut self, message: &str,
+                parameters: &[Parameters]) -> Result<(), Vec<String>> {
+        let user = self.user.clone();
+        self.send_to(user.as_ref(), message, parameters)
+    }
+
+    /// Like `send`, but pushes the message to the given user key instead of
+    /// this Client's user.
+    pub fn send_to(&mut self, user: &str, message: &str,
+                   parameters: &[Parameters]) -> Result<(), Vec<String>> {
+        self.retrying(|client| {
+            if client.options.overflow == Overflow::Split {
+                let results = client.send_split_to(user, message, parameters);
+                return results.into_iter().fold(Ok(()), |result, r| result.and(r));
+            }
+            client.send_one(user, message, parameters).map(|_| ())
+        })
+    }
+
+    /// Like `send`, but returns what happened to the message: its request ID,
+    /// whether it was truncated, and the link to its full text if uploaded.
+    pub fn send_detailed(&mut self, message: &str,
+                         parameters: &[Parameters]) -> Result<SendOutcome, Vec<String>> {
+        let user = self.user.clone();
+        self.retrying(|client| client.send_one(user.as_ref(), message, parameters))
+    }
+
+    // Run a send, running it again after a delay if it fails with a retryable
+    // error, up to `Options::retries` times. Requests that were delivered by
+    // an earlier attempt aren't sent again (see `send_one`).
+    fn retrying<T, F>(&mut self, send: F) -> Result<T, Vec<String>>
+        where F: FnMut(&mut Client) -> Result<T, Vec<String>> {
+        let result = self.backoff("send", send, |errors| {
+            if is_retryable(errors) {
+                Some(retry_after(errors))
+            }
+            else {
+                None
+            }
+        });
+        self.delivered.clear();
+        self.uploaded.clear();
+        result
+    }
+
+    // Run a request, running it again after a delay if it fails with an error
+    // that `retry_wait` says is retryable, up to `Options::retries` times,
+    // doubling the delay each time. `retry_wait` returns None for errors that
+    // aren't retryable, and otherwise how long the service asked to wait, if
+    // it did.
+    fn backoff<T, E, F, W>(&mut self, what: &str, mut run: F,
+                           retry_wait: W) -> Result<T, E>
+        where E: fmt::Debug,
+              F: FnMut(&mut Client) -> Result<T, E>,
+              W: Fn(&E) -> Option<Option<u64>> {
+        let mut delay = self.options.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            let result = run(self);
+            let wait = match result {
+                Err(ref error) if attempt < self.options.retries => retry_wait(error),
+                _ => None
+            };
+            // Wait as long as the service asks, if it rate limited us:
+            let wait_ms = match wait {
+                Some(Some(secs)) => secs * 1000,
+                Some(None) if self.options.retry_jitter => {
+                    self.random() % (delay * 1000 + 1)
+                },
+                Some(None) => delay * 1000,
+                None => return result
+            };
+            if let Err(ref error) = result {
+                warn!("{} failed, retrying in {}ms: {:?}", what, wait_ms, error);
+            }
+            thread::sleep(Duration::from_millis(wait_ms));
+            delay = delay * 2;
+            attempt += 1;
+        }
+    }
+
+    // The next number from a small xorshift generator; good enough for
+    // spreading out retries, and nothing else.
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Sends a message to this Client's user as several notifications if it's
+    /// too long for one, splitting it at character boundaries and prefixing
+    /// each part's title with its part number. Returns the result of sending
+    /// each part, in order.
+    pub fn send_split(&mut self, message: &str,
+                      parameters: &[Parameters]) -> Vec<Result<(), Vec<String>>> {
+        let user = self.user.clone();
+        let results = self.send_split_to(user.as_ref(), message, parameters);
+        self.delivered.clear();
+        self.uploaded.clear();
+        results
+    }
+
+    // Upload a message's full text for the current send, reusing the link
+    // from an earlier attempt if there was one.
+    fn upload_for_send(&mut self, message: &str,
+                       title: String) -> Result<(String, String), SnippetError> {
+        let key = (message.to_string(), title.clone());
+        if let Some(link) = self.uploaded.get(&key) {
+            return Ok(link.clone());
+        }
+        let link = try!(self.upload_snippet_retrying(message, title));
+        self.uploaded.insert(key, link.clone());
+        Ok(link)
+    }
+
+    fn send_split_to(&mut self, user: &str, message: &str,
+                     parameters: &[Parameters]) -> Vec<Result<(), Vec<String>>> {
+        let limit = self.options.max_message_len;
+        let chars: Vec<char> = message.chars().collect();
+        if chars.len() <= limit {
+            return vec![self.send_one(user, message, parameters).map(|_| ())];
+        }
+
+        let mut title = "po".to_string();
+        for p in parameters.iter() {
+            if let Title(ref t) = *p {
+                title = t.clone();
+            }
+        }
+        // Check the title as given once, since each part's title is cut down
+        // to make room for its part number:
+        if title.chars().count() > TITLE_LIMIT {
+            return vec![Err(vec![format!("title exceeds {} characters", TITLE_LIMIT)])];
+        }
+        let parts: Vec<String> = chars.chunks(limit)
+                                      .map(|part| part.iter().cloned().collect())
+                                      .collect();
+        let count = parts.len();
+
+        // Upload the whole message once, rather than each part, and link it
+        // from every part:
+        let mut link = None;
+        if parameters.contains(&Gist) {
+            match self.upload_for_send(message, title.clone()) {
+                Ok(uploaded) => link = Some(uploaded),
+                Err(ref e) if self.options.gist_required => {
+                    return vec![Err(vec![format!("couldn't upload full message: {}", e)])];
+                },
+                Err(e) => warn!("couldn't upload full message: {}", e)
+            }
+        }
+
+        parts.iter().enumerate().map(|(i, part)| {
+            let mut part_parameters: Vec<Parameters> = parameters.iter().filter(|p| {
+                match **p {
+                    Title(_) | Gist => false,
+                    URL(_) | URLTitle(_) => link.is_none(),
+                    _ => true
+                }
+            }).cloned().collect();
+            let number = format!("({}/{}) ", i + 1, count);
+            let room = TITLE_LIMIT - number.chars().count();
+            let part_title: String = title.chars().take(room).collect();
+            part_parameters.push(Title(number + &part_title));
+            if let Some((ref url, ref url_title)) = link {
+                part_parameters.push(URL(url.clone()));
+                part_parameters.push(URLTitle(url_title.clone()));
+            }
+            let key = part_key(user, message, parameters, i);
+            self.send_keyed(key, user, part.as_ref(), part_parameters.as_ref()).map(|_| ())
+        }).collect()
+    }
+
+    // Push a single notification, truncating the message or refusing to send
+    // it if it's too long.
+    fn send_one(&mut self, user: &str, message: &str,
+                parameters: &[Parameters]) -> Result<SendOutcome, Vec<String>> {
+        let key = request_key(user, message, parameters);
+        self.send_keyed(key, user, message, parameters)
+    }
+
+    // Like `send_one`, but identifying the request by the given key (see
+    // `request_key`) when checking whether it was already delivered.
+    fn send_keyed(&mut self, key: u64, user: &str, message: &str,
+                  parameters: &[Parameters]) -> Result<SendOutcome, Vec<String>> {
+        // Pushover rejects empty messages with an opaque error, so catch them
+        // here:
+        let message = if message.trim() != "" {
+            message
+        }
+        else if self.options.allow_empty {
+            " "
+        }
+        else {
+            return Err(vec![format!("message is empty")]);
+        };
+        // `message` stays the complete input, for uploading with the Gist
+        // parameter; only `msg` is cut down to fit.
+        let limit = self.options.max_message_len;
+        let length = message.chars().count();
+        if length > limit && self.options.overflow == Overflow::Error &&
+                !parameters.contains(&Gist) {
+            return Err(vec![format!("message exceeds {} characters", limit)]);
+        }
+
+        // Pushover counts characters, not bytes, so truncate at a character
+        // boundary:
+        let msg: String = if length > limit {
+            message.chars().take(limit).collect()
+        }
+        else {
+            message.to_string()
+        };
+
+        // If an earlier attempt of this send already delivered this exact
+        // request, its response may simply have been lost on a later part;
+        // don't notify the user twice:
+        if let Some(outcome) = self.delivered.get(&key) {
+            debug!("request {:016x} already delivered, not resending", key);
+            return Ok(outcome.clone());
+        }
+        let mut title = "po".to_string();
+        let mut gist_url = None;
+        let mut debug = false;
+        let priority = parameters.iter()
+                                 .filter_map(|p| if let Priority(p) = *p { Some(p) } else { None })
+                                 .last()
+                                 .unwrap_or(0);
+        let emergency = parameters.iter().any(|p| *p == Priority(2));
+        if parameters.iter().any(|p| *p == Priority(-2)) &&
+                parameters.iter().any(|p| if let Sound(_) = *p { true } else { false }) {
+            warn!("priority -2 messages don't alert the user, so the sound is ignored");
+        }
+
+        let mut notification = vec![
+            ("token".to_string(), self.token.clone()),
+            ("user".to_string(), user.to_string()),
+            ("message".to_string(), msg)];
+
+        // Copy the parameters collection into a vector we own; slightly
+        // inefficent but much more convenient for the caller.
+        let mut para = Vec::with_capacity(parameters.len());
+        for p in parameters.iter() {
+            para.push(p.clone());
+        }
+
+        for parameter in para.into_iter() {
+            match parameter {
+                Priority(p)  => notification.push(("priority".to_string(), p.to_string())),
+                Title(t)     => {
+                    // Pushover rejects titles longer than 250 characters, and
+                    // the title also names the Gist file, so check it up
+                    // front:
+                    if t.chars().count() > TITLE_LIMIT {
+                        return Err(vec![format!("title exceeds {} characters",
+                                                TITLE_LIMIT)]);
+                    }
+                    notification.push(("title".to_string(), t.clone()));
+                    title = t;
+                },
+                Device(d)    => notification.push(("device".to_string(), d)),
+                Devices(ds)  => {
+                    if ds.is_empty() || ds.iter().any(|d| d.trim() == "") {
+                        return Err(vec![format!("device names must not be empty")]);
+                    }
+                    notification.push(("device".to_string(), ds.join(",")));
+                },
+                Sound(s)     => {
+                    // Pushover's own sounds are lowercase, but "Cosmic" is
+                    // a natural thing to type; custom sounds are sent as
+                    // given:
+                    let lower = s.to_lowercase();
+                    let sound = if SOUNDS.iter().any(|&(name, _)| name == lower) {
+                        lower
+                    }
+                    else {
+                        s
+                    };
+                    notification.push(("sound".to_string(), sound));
+                },
+                URL(u)       => notification.push(("url".to_string(), u)),
+                URLTitle(ut) => notification.push(("url_title".to_string(), ut)),
+                HTML         => notification.push(("html".to_string(), "1".to_string())),
+                Tags(t)      => notification.push(("tags".to_string(), t.join(","))),
+                Callback(c)  => {
+                    if !emergency {
+                        return Err(vec![format!("callback requires emergency priority (2)")]);
+                    }
+                    notification.push(("callback".to_string(), c));
+                },
+                Retry(r)     => {
+                    if r < 30 {
+                        return Err(vec![format!("retry must be at least 30 seconds")]);
+                    }
+                    notification.push(("retry".to_string(), r.to_string()));
+                },
+                Expire(e)    => {
+                    if e > 10800 {
+                        return Err(vec![format!("expire must be at most 10800 seconds")]);
+                    }
+                    notification.push(("expire".to_string(), e.to_string()));
+                },
+                Gist         => {
+                    match self.upload_for_send(message, title.clone()) {
+                        Ok((snippet_url, snippet_title)) => {
+                            info!("uploaded full message to {}", snippet_url);
+                            gist_url = Some(snippet_url.clone());
+                            notification.push(("url".to_string(), snippet_url));
+                            notification.push(("url_title".to_string(), snippet_title));
+                        },
+                        Err(e) => {
+                            if self.options.gist_required {
+                                return Err(vec![format!("couldn't upload full message: {}",
+                                                        e)]);
+                            }
+                            warn!("couldn't upload full message: {}", e)
+                        }
+                    }
+                },
+                Parameters::Attachment(a) => {
+                    if a.bytes.len() > ATTACHMENT_LIMIT {
+                        return Err(vec![format!("attachment exceeds {} bytes",
+                                                ATTACHMENT_LIMIT)]);
+                    }
+                    notification.push(("attachment_base64".to_string(),
+                                       a.bytes.to_base64(base64::STANDARD)));
+                    notification.push(("attachment_type".to_string(), a.mime_type));
+                },
+                Field(k, v)  => notification.push((k, v)),
+                Debug        => debug = true
+            }
+        }
+
+        let body = form_body(notification.clone());
+        let body_ref: &str = body.as_ref();
+        if debug {
+            let fields: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
+            debug!("push parameters: {}", fields.join(", "));
+            debug!("push fields:\n{}", field_table(&notification));
+            debug!("push body:\n{}", body);
+        }
+        let url = format!("{}/messages.json", self.options.pushover_url);
+        let invalid_sound = self.options.invalid_sound.clone();
+        let response = match self.api_post(url.as_ref(), body_ref) {
+            Err(ref errors) if invalid_sound != InvalidSound::Error &&
+                               errors.iter().any(|e| e == "sound is invalid") => {
+                let mut resend: Vec<(String, String)> =
+                    notification.into_iter().filter(|&(ref k, _)| k != "sound").collect();
+                if let InvalidSound::Fallback(sound) = invalid_sound {
+                    warn!("sound rejected as invalid; resending with {}", sound);
+                    resend.push(("sound".to_string(), sound));
+                }
+                else {
+                    warn!("sound rejected as invalid; resending without a sound");
+                }
+                let resend_body = form_body(resend);
+                let resend_ref: &str = resend_body.as_ref();
+                try!(self.api_post(url.as_ref(), resend_ref))
+            },
+            result => try!(result)
+        };
+        let response_ref: &str = response.as_ref();
+
+        match json::decode::<SentJson>(response_ref) {
+            Ok(sent) => {
+                let outcome = SendOutcome {
+                    request_id: sent.request,
+                    truncated: length > limit,
+                    gist_url: gist_url,
+                    receipt: sent.receipt,
+                    priority: priority
+                };
+                self.delivered.insert(key, outcome.clone());
+                Ok(outcome)
+            },
+            Err(_) => Err(vec![format!("malformed message response")])
+        }
+    }
+
+    /// Cancels retries of every emergency-priority notification sent with the
+    /// given tag (see `cancel_by_tag`).
+    pub fn cancel_by_tag(&mut self, tag: &str) -> Result<(), Vec<String>> {
+        let url = format!("{}/receipts/cancel_by_tag/{}.json", self.options.pushover_url,
+                          utf8_percent_encode(tag, USERINFO_ENCODE_SET));
+        let body = form_body(vec![("token".to_string(), self.token.clone())]);
+        let body_ref: &str = body.as_ref();
+        self.api_post(url.as_ref(), body_ref).map(|_| ())
+    }
+
+    /// Cancels retries of the emergency-priority notification with the given
+    /// receipt (see `cancel_receipt`).
+    pub fn cancel_receipt(&mut self, receipt: &str) -> Result<(), Vec<String>> {
+        let url = format!("{}/receipts/{}/cancel.json", self.options.pushover_url,
+                          utf8_percent_encode(receipt, USERINFO_ENCODE_SET));
+        let body = form_body(vec![("token".to_string(), self.token.clone())]);
+        let body_ref: &str = body.as_ref();
+        self.api_post(url.as_ref(), body_ref).map(|_| ())
+    }
+
+    /// Checks that this Client's API token and user key are accepted by
+    /// Pushover, returning the names of the user's active devices (see
+    /// `validate_credentials`).
+    pub fn validate_credentials(&mut self) -> Result<Vec<String>, Vec<String>> {
+        let url = format!("{}/users/validate.json", self.options.pushover_url);
+        let body = form_body(vec![("token".to_string(), self.token.clone()),
+                                  ("user".to_string(), self.user.clone())]);
+        let body_ref: &str = body.as_ref();
+        let response = try!(self.api_post(url.as_ref(), body_ref));
+        let response_ref: &str = response.as_ref();
+
+        match json::decode::<ValidateJson>(response_ref) {
+            Ok(v) => Ok(v.devices),
+            Err(_) => Err(vec![format!("malformed validate response")])
+        }
+    }
+
+    /// Returns this Client's user's active devices, leaving out the excluded
+    /// ones, for sending to every device but some with `Parameters::Devices`.
+    /// Device names are compared case-insensitively. Returns an error if no
+    /// devices are left.
+    pub fn devices_except(&mut self, excluded: &[String]) -> Result<Vec<String>, Vec<String>> {
+        let excluded: Vec<String> = excluded.iter().map(|d| d.to_lowercase()).collect();
+        let devices: Vec<String> = try!(self.validate_credentials())
+            .into_iter()
+            .filter(|d| !excluded.contains(&d.to_lowercase()))
+            .collect();
+
+        if devices.is_empty() {
+            return Err(vec![format!("no devices left after exclusions")]);
+        }
+        Ok(devices)
+    }
+
+    /// Subscribes this Client's user to the application's subscription with
+    /// the given code, returning the subscribed user key to send to (see
+    /// `subscribe`).
+    pub fn subscribe(&mut self, subscription_code: &str, device: Option<&str>,
+                     sound: Option<&str>) -> Result<String, Vec<String>> {
+        let url = format!("{}/subscriptions/migrate.json", self.options.pushover_url);
+        let mut fields = vec![("token".to_string(), self.token.clone()),
+                              ("subscription".to_string(), subscription_code.to_string()),
+                              ("user".to_string(), self.user.clone())];
+        if let Some(device) = device {
+            fields.push(("device_name".to_string(), device.to_string()));
+        }
+        if let Some(sound) = sound {
+            fields.push(("sound".to_string(), sound.to_string()));
+        }
+        let body = form_body(fields);
+        let body_ref: &str = body.as_ref();
+        let response = try!(self.api_post(url.as_ref(), body_ref));
+        let response_ref: &str = response.as_ref();
+
+        match json::decode::<SubscriptionJson>(response_ref) {
+            Ok(s) => Ok(s.subscribed_user_key),
+            Err(_) => Err(vec![format!("malformed subscription response")])
+        }
+    }
+
+    /// Fetches the status of an emergency-priority notification's receipt
+    /// (see `receipt_status`).
+    pub fn receipt_status(&mut self, receipt: &str) -> Result<ReceiptStatus, Vec<String>> {
+        let url = format!("{}/receipts/{}.json?token={}", self.options.pushover_url,
+                          utf8_percent_encode(receipt, USERINFO_ENCODE_SET),
+                          utf8_percent_encode(self.token.as_ref(), USERINFO_ENCODE_SET));
+        let body = try!(self.api_get(url.as_ref()));
+        let body_ref: &str = body.as_ref();
+
+        match json::decode::<ReceiptJson>(body_ref) {
+            Ok(r) => Ok(ReceiptStatus {
+                acknowledged: r.acknowledged == 1,
+                acknowledged_at: r.acknowledged_at,
+                acknowledged_by: r.acknowledged_by,
+                acknowledged_by_device: r.acknowledged_by_device,
+                last_delivered_at: r.last_delivered_at,
+                expired: r.expired == 1,
+                expires_at: r.expires_at,
+                called_back: r.called_back == 1,
+                called_back_at: r.called_back_at
+            }),
+            Err(_) => Err(vec![format!("malformed receipt response")])
+        }
+    }
+
+    /// Fetches this Client's application's monthly message allowance (see
+    /// `app_limits`).
+    pub fn app_limits(&mut self) -> Result<RateLimit, Vec<String>> {
+        let url = format!("{}/apps/limits.json?token={}", self.options.pushover_url,
+                          utf8_percent_encode(self.token.as_ref(), USERINFO_ENCODE_SET));
+        let body = try!(self.api_get(url.as_ref()));
+        let body_ref: &str = body.as_ref();
+
+        match json::decode::<LimitsJson>(body_ref) {
+            Ok(l) => Ok(RateLimit {
+                limit: l.limit,
+                remaining: l.remaining,
+                reset: l.reset
+            }),
+            Err(_) => Err(vec![format!("malformed limits response")])
+        }
+    }
+
+    /// Sets the count shown on this Client's user's Glances widgets, such as
+    /// a badge on a watch face (see `update_glance_count`).
+    pub fn update_glance_count(&mut self, count: i64) -> Result<(), Vec<String>> {
+        self.update_glance(&Glance { count: Some(count), ..Glance::default() })
+    }
+
+    /// Sets the fields of this Client's user's Glances widgets given in a
+    /// Glance, all in one request (see `update_glance`).
+    pub fn update_glance(&mut self, glance: &Glance) -> Result<(), Vec<String>> {
+        let mut fields = Vec::new();
+
+        if let Some(ref title) = glance.title {
+            fields.push(("title".to_string(), title.clone()));
+        }
+        if let Some(ref text) = glance.text {
+            fields.push(("text".to_string(), text.clone()));
+        }
+        if let Some(ref subtext) = glance.subtext {
+            fields.push(("subtext".to_string(), subtext.clone()));
+        }
+        if let Some(count) = glance.count {
+            if count < std::i32::MIN as i64 || count > std::i32::MAX as i64 {
+                return Err(vec![format!("glance count must be a 32-bit integer")]);
+            }
+            fields.push(("count".to_string(), count.to_string()));
+        }
+        if let Some(percent) = glance.percent {
+            if percent > 100 {
+                return Err(vec![format!("glance percent must be from 0 to 100")]);
+            }
+            fields.push(("percent".to_string(), percent.to_string()));
+        }
+        if fields.is_empty() {
+            return Err(vec![format!("glance has no fields to update")]);
+        }
+
+        let url = format!("{}/glances.json", self.options.pushover_url);
+        let mut request = vec![("token".to_string(), self.token.clone()),
+                               ("user".to_string(), self.user.clone())];
+        request.extend(fields);
+        let body = form_body(request);
+        let body_ref: &str = body.as_ref();
+        self.api_post(url.as_ref(), body_ref).map(|_| ())
+    }
+
+    /// Like `send_and_wait_ack`, but also raises this Client's user's Glances
+    /// count by one each time the receipt is polled and found unacknowledged,
+    /// so that a watch shows a growing badge while the alert is outstanding.
+    /// The count is reset to 0 once the message is acknowledged or expires
+    /// (see `send_and_escalate_glance`).
+    pub fn send_and_escalate_glance(&mut self, message: &str, retry: u32, expire: u32,
+                                    poll_interval: u64) -> Result<AckResult, Vec<String>> {
+        let outcome = try!(self.send_detailed(message, &[Priority(2), Retry(retry),
+                                                         Expire(expire)]));
+        let receipt = match outcome.receipt {
+            Some(receipt) => receipt,
+            None => return Err(vec![format!("no receipt for emergency message")])
+        };
+
+        let mut count = 1;
+        try!(self.update_glance_count(count));
+        loop {
+            thread::sleep(Duration::from_secs(poll_interval));
+            let status = try!(self.receipt_status(receipt.as_ref()));
+            if status.acknowledged || status.expired {
+                if let Err(errors) = self.update_glance_count(0) {
+                    warn!("couldn't reset glance count: {:?}", errors);
+                }
+                return Ok(AckResult {
+                    acknowledged: status.acknowledged,
+                    acknowledged_by: status.acknowledged_by,
+                    acknowledged_by_device: status.acknowledged_by_device,
+                    acknowledged_at: status.acknowledged_at
+                });
+            }
+            count += 1;
+            try!(self.update_glance_count(count));
+        }
+    }
+
+    /// Sends an emergency-priority message to this Client's user, then polls
+    /// its receipt every `poll_interval` seconds until it's acknowledged or
+    /// expires (see `send_and_wait_ack`).
+    pub fn send_and_wait_ack(&mut self, message: &str, retry: u32, expire: u32,
+                             poll_interval: u64) -> Result<AckResult, Vec<String>> {
+        let outcome = try!(self.send_detailed(message, &[Priority(2), Retry(retry),
+                                                         Expire(expire)]));
+        let receipt = match outcome.receipt {
+            Some(receipt) => receipt,
+            None => return Err(vec![format!("no receipt for emergency message")])
+        };
+
+        loop {
+            thread::sleep(Duration::from_secs(poll_interval));
+            let status = try!(self.receipt_status(receipt.as_ref()));
+            if status.acknowledged || status.expired {
+                return Ok(AckResult {
+                    acknowledged: status.acknowledged,
+                    acknowledged_by: status.acknowledged_by,
+                    acknowledged_by_device: status.acknowledged_by_device,
+                    acknowledged_at: status.acknowledged_at
+                });
+            }
+        }
+    }
+}
+
+/// Post a message body with a given title to GitHub Gist and return the Gist's
+/// URLs and ID.
+pub fn gist(message: &str, title: String) -> Result<GistResult, SnippetError> {
+    gist_with_options(message, title, &Options::default())
 }
 
-#[derive(RustcEncodable)]
-struct GistPost {
-    files: BTreeMap<String, json::Json>
+/// Like `gist`, but with the given request Options.
+pub fn gist_with_options(message: &str, title: String,
+                         options: &Options) -> Result<GistResult, SnippetError> {
+    // Snippet uploads don't need Pushover credentials:
+    Client::with_options("", "", options.clone()).gist(message, title)
 }
 
-#[derive(RustcDecodable)]
-struct GistResponse {
-    html_url: String
+/// Delete a Gist, given its ID (see `GistResult::id`) and a GitHub personal
+/// access token for the account that created it, such as to keep sensitive
+/// output from lingering after a notification is read. Anonymous Gists can't
+/// be deleted; create Gists with `Options::github_token` to delete them later.
+pub fn delete_gist(id: &str, github_token: &str) -> Result<(), SnippetError> {
+    delete_gist_with_options(id, github_token, &Options::default())
 }
 
-fn api_error(response_body: &str) -> Result<(), Vec<String>> {
-    let response: MessagesJson = json::decode(response_body).unwrap();
+/// Like `delete_gist`, but with the given request Options.
+pub fn delete_gist_with_options(id: &str, github_token: &str,
+                                options: &Options) -> Result<(), SnippetError> {
+    Client::with_options("", "", options.clone()).delete_gist(id, github_token)
+}
 
-    if response.status != 1 {
-        return Err(response.errors);
-    }
+/// Delete the Gists uploaded by po more than `max_age` seconds ago, given a
+/// GitHub personal access token, returning their IDs (see
+/// `Client::cleanup_gists`).
+pub fn cleanup_gists(github_token: &str, max_age: i64) -> Result<Vec<String>, SnippetError> {
+    Client::new("", "").cleanup_gists(github_token, max_age)
+}
 
-    Err(vec![format!("general API error")])
+/// Post a message body with a given title as a private snippet on a GitLab
+/// instance, using a personal access token, and return the snippet's URL.
+pub fn gitlab_snippet(message: &str, title: String, base_url: &str,
+                      token: &str) -> Result<String, SnippetError> {
+    gitlab_snippet_with_options(message, title, base_url, token,
+                                &Options::default())
 }
 
-/// Post a message body with a given title to GitHub Gist and return the Gist's
-/// URL.
-pub fn gist(message: &str, title: String) -> Result<String, (u32, String)> {
-    let mut content = BTreeMap::new();
-    content.insert("content".to_string(), message.to_json());
-    let mut gist_file = BTreeMap::new();
-    gist_file.insert(title, content.to_json());
-    let gist = GistPost {
-        files: gist_file
-    };
+/// Like `gitlab_snippet`, but with the given request Options.
+pub fn gitlab_snippet_with_options(message: &str, title: String, base_url: &str,
+                                   token: &str,
+                                   options: &Options) -> Result<String, SnippetError> {
+    Client::with_options("", "", options.clone())
+        .gitlab_snippet(message, title, base_url, token)
+}
 
-    if let Ok(json) = json::encode(&gist) {
-        let mut handle = http::handle();
-        let json_ref: &str = json.as_ref();
-        let upload = handle
-                        .post("https://api.github.com/gists", json_ref)
-                        .header("Content-Type", "application/json")
-                        .header("User-Agent", "po");
-        if let Ok(res) = upload.exec() {
-            if res.get_code() == 201 || res.get_code() == 200 {
-                let body = std::str::from_utf8(res.get_body()).unwrap();
-                let response: GistResponse = json::decode(body).unwrap();
-                return Ok(response.html_url);
-            }
-        }
-    }
+/// Post a message body with a given title to an anonymous paste service at
+/// the given URL (see `SnippetBackend::Paste`) and return the paste's URL.
+pub fn paste(message: &str, title: String,
+             url: &str) -> Result<String, SnippetError> {
+    paste_with_options(message, title, url, &Options::default())
+}
+
+/// Like `paste`, but with the given request Options.
+pub fn paste_with_options(message: &str, title: String, url: &str,
+                          options: &Options) -> Result<String, SnippetError> {
+    Client::with_options("", "", options.clone()).paste(message, title, url)
+}
+
+/// Upload a message body with a given title to the snippet backend set in the
+/// given Options, returning the link to it and a title for that link.
+pub fn upload_snippet(message: &str, title: String,
+                      options: &Options) -> Result<(String, String), SnippetError> {
+    Client::with_options("", "", options.clone()).upload_snippet(message, title)
+}
 
-    Err((0, format!("Generic: Couldn't post to Gist.")))
+/// Like `upload_snippet`, but retrying failed uploads as the given Options
+/// allow (see `Client::upload_snippet_retrying`).
+pub fn upload_snippet_retrying(message: &str, title: String,
+                               options: &Options) -> Result<(String, String), SnippetError> {
+    Client::with_options("", "", options.clone()).upload_snippet_retrying(message, title)
 }
+
 /// Pushes a message using the Pushover API, with the specified API token,
 /// user key, message body, and array of optional Parameters.
 pub fn push(token: &str, user: &str, message: &str,
                        parameters: &[Parameters]) -> Result<(), Vec<String>> {
-    // Keep these here for now to satisfy the borrow checker:
-    let msg = if message.len() > 1024 {
-        message[0..1024].as_ref()
-    }
-    else {
-        message
+    push_with_options(token, user, message, parameters, &Options::default())
+}
+
+/// Like `push`, but with the given request Options.
+pub fn push_with_options(token: &str, user: &str, message: &str,
+                         parameters: &[Parameters],
+                         options: &Options) -> Result<(), Vec<String>> {
+    Client::with_options(token, user, options.clone()).send(message, parameters)
+}
+
+/// Like `push`, but also sends each of the `extra` fields and values as is,
+/// for Pushover API features that `Parameters` doesn't model yet (see
+/// `Parameters::Field`). The extra fields bypass po's validation entirely;
+/// prefer `Parameters` wherever it covers what you need.
+pub fn push_raw(token: &str, user: &str, message: &str,
+                extra: &[(String, String)]) -> Result<(), Vec<String>> {
+    let parameters: Vec<Parameters> = extra.iter()
+                                           .map(|&(ref k, ref v)| Field(k.clone(), v.clone()))
+                                           .collect();
+    push(token, user, message, parameters.as_ref())
+}
+
+/// Sets the count shown on a user's Glances widgets, with the specified API
+/// token and user key.
+pub fn update_glance_count(token: &str, user: &str, count: i64) -> Result<(), Vec<String>> {
+    Client::new(token, user).update_glance_count(count)
+}
+
+/// Sets the fields given in a Glance on a user's Glances widgets in one
+/// request, with the specified API token and user key. Fields left as None
+/// are unchanged.
+pub fn update_glance(token: &str, user: &str, glance: &Glance) -> Result<(), Vec<String>> {
+    Client::new(token, user).update_glance(glance)
+}
+
+/// Sends an emergency-priority message like `send_and_wait_ack`, raising the
+/// user's Glances count by one every `poll_interval` seconds until a user
+/// acknowledges it or it expires, then resetting the count to 0.
+pub fn send_and_escalate_glance(token: &str, user: &str, message: &str, retry: u32,
+                                expire: u32, poll_interval: u64) -> Result<AckResult, Vec<String>> {
+    Client::new(token, user).send_and_escalate_glance(message, retry, expire, poll_interval)
+}
+
+/// Sends an emergency-priority message with the given retry and expire times
+/// (see `Parameters::Retry` and `Parameters::Expire`), then blocks, checking
+/// its receipt every `poll_interval` seconds, until a user acknowledges it or
+/// it expires. Returns whether it was acknowledged, and by whom.
+pub fn send_and_wait_ack(token: &str, user: &str, message: &str, retry: u32,
+                         expire: u32, poll_interval: u64) -> Result<AckResult, Vec<String>> {
+    Client::new(token, user).send_and_wait_ack(message, retry, expire, poll_interval)
+}
+
+/// Like `push`, but returns what happened to the message (see `SendOutcome`).
+pub fn push_detailed(token: &str, user: &str, message: &str,
+                     parameters: &[Parameters]) -> Result<SendOutcome, Vec<String>> {
+    Client::new(token, user).send_detailed(message, parameters)
+}
+
+/// Pushes a message like `push`, but as several notifications if it's too long
+/// for one (see `Overflow::Split`), returning the result of sending each part.
+pub fn push_split(token: &str, user: &str, message: &str,
+                  parameters: &[Parameters]) -> Vec<Result<(), Vec<String>>> {
+    Client::new(token, user).send_split(message, parameters)
+}
+
+/// Cancels retries of every emergency-priority notification sent with the
+/// given tag (see `Parameters::Tags`), using the specified API token.
+pub fn cancel_by_tag(token: &str, tag: &str) -> Result<(), Vec<String>> {
+    cancel_by_tag_with_options(token, tag, &Options::default())
+}
+
+/// Like `cancel_by_tag`, but with the given request Options.
+pub fn cancel_by_tag_with_options(token: &str, tag: &str,
+                                  options: &Options) -> Result<(), Vec<String>> {
+    Client::with_options(token, "", options.clone()).cancel_by_tag(tag)
+}
+
+/// Subscribes a user, by user key, to an application's subscription, using
+/// the application's API token and the subscription code from its Pushover
+/// dashboard, optionally limited to one device and with a default sound.
+/// Returns the subscribed user key, which the application sends to from then
+/// on in place of the user's own key, so users don't have to copy their keys
+/// into the application.
+pub fn subscribe(token: &str, subscription_code: &str, user: &str, device: Option<&str>,
+                 sound: Option<&str>) -> Result<String, Vec<String>> {
+    Client::new(token, user).subscribe(subscription_code, device, sound)
+}
+
+/// Cancels retries of the emergency-priority notification with the given
+/// receipt (see `SendOutcome::receipt`), using the specified API token.
+pub fn cancel_receipt(token: &str, receipt: &str) -> Result<(), Vec<String>> {
+    Client::new(token, "").cancel_receipt(receipt)
+}
+
+/// Cancels retries of each of a list of emergency-priority notifications, by
+/// receipt, using the specified API token, such as to silence every
+/// outstanding alert at once when they weren't tagged (otherwise, see
+/// `cancel_by_tag`). Every receipt is tried, even after a failure.
+pub fn cancel_all(token: &str, receipts: &[String]) -> CancelSummary {
+    let mut client = Client::new(token, "");
+    let mut summary = CancelSummary {
+        cancelled: Vec::new(),
+        failed: Vec::new()
     };
-    let mut title = "po".to_string();
-    let mut debug = false;
-
-    let mut notification = vec![
-        ("token".to_string(), token.to_string()),
-        ("user".to_string(), user.to_string()),
-        ("message".to_string(), msg.to_string())];
-
-    // Copy the parameters collection into a vector we own; slightly inefficent
-    // but much more convenient for the caller.
-    let mut para = Vec::with_capacity(parameters.len());
-    for p in parameters.iter() {
-        para.push(p.clone());
-    }
-
-    for parameter in para.into_iter() {
-        match parameter {
-            Priority(p)  => notification.push(("priority".to_string(), p.to_string())),
-            Title(t)     => {
-                notification.push(("title".to_string(), t.clone()));
-                title = t;
-            },
-            Device(d)    => notification.push(("device".to_string(), d)),
-            Sound(s)     => notification.push(("sound".to_string(), s)),
-            URL(u)       => notification.push(("url".to_string(), u)),
-            URLTitle(ut) => notification.push(("url_title".to_string(), ut)),
-            Gist         => {
-                if let Ok(gist_url) = gist(message, title.clone()) {
-                    notification.push(("url".to_string(), gist_url));
-                    notification.push(("url_title".to_string(),
-                        "Full Output (GitHub Gist)".to_string()));
-                }
-            },
-            Debug        => debug = true
+
+    for receipt in receipts.iter() {
+        match client.cancel_receipt(receipt.as_ref()) {
+            Ok(()) => summary.cancelled.push(receipt.clone()),
+            Err(errors) => summary.failed.push((receipt.clone(), errors))
         }
     }
+    summary
+}
+
+/// Checks that an API token and user key are accepted by Pushover, returning
+/// the names of the user's active devices.
+pub fn validate_credentials(token: &str, user: &str) -> Result<Vec<String>, Vec<String>> {
+    validate_credentials_with_options(token, user, &Options::default())
+}
+
+/// Like `validate_credentials`, but with the given request Options.
+pub fn validate_credentials_with_options(token: &str, user: &str,
+                                         options: &Options) -> Result<Vec<String>, Vec<String>> {
+    Client::with_options(token, user, options.clone()).validate_credentials()
+}
+
+/// Pushes a batch of messages with the specified API token, each job being a
+/// (user key, message body, Parameters) triple. Up to `concurrency` messages
+/// are sent in parallel; the results are returned in the same order as the
+/// jobs.
+pub fn push_batch(token: &str, jobs: Vec<(String, String, Vec<Parameters>)>,
+                  concurrency: usize) -> Vec<Result<(), Vec<String>>> {
+    let count = jobs.len();
+    let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let (tx, rx) = mpsc::channel();
+    let workers = if concurrency == 0 { 1 } else { concurrency };
+
+    for _ in 0..workers {
+        let queue = queue.clone();
+        let tx = tx.clone();
+        let token = token.to_string();
+        thread::spawn(move || {
+            let mut client = Client::new(token.as_ref(), "");
+            loop {
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((i, (user, message, parameters))) => {
+                        let result = client.send_to(user.as_ref(), message.as_ref(),
+                                                    parameters.as_ref());
+                        tx.send((i, result)).unwrap();
+                    },
+                    None => break
+                }
+            }
+        });
+    }
+    drop(tx);
 
-    let body = form_urlencoded::serialize(notification.into_iter());
-    let body_ref: &str = body.as_ref();
-    if debug {
-        println!("push body:\n{}", body);
+    let mut results: Vec<Option<Result<(), Vec<String>>>> =
+        (0..count).map(|_| None).collect();
+    for (i, result) in rx.iter() {
+        results[i] = Some(result);
     }
-    let mut handle = http::handle();
-    let message = handle
-                    .post("https://api.pushover.net/1/messages.json", body_ref)
-                    .header("Content-Type", "application/x-www-form-urlencoded");
-    match message.exec() {
-        Ok(res) => {
-            match res.get_code() {
-                200 => Ok(()),
-                400...499 => api_error(std::str::from_utf8(res.get_body()).unwrap()),
-                n => Err(vec![format!("API error {}", n)])
+    results.into_iter()
+           .map(|r| r.unwrap_or(Err(vec![format!("send thread panicked")])))
+           .collect()
+}
+
+/// Best-effort notifications sent in the background, for callers such as web
+/// handlers that shouldn't wait on Pushover. Failures are logged with the
+/// `log` crate rather than returned.
+pub struct Background {
+    sends: Vec<thread::JoinHandle<()>>
+}
+
+impl Background {
+    /// Creates a Background with no sends in flight.
+    pub fn new() -> Background {
+        Background { sends: Vec::new() }
+    }
+
+    /// Pushes a message on its own thread (see `push`), returning immediately.
+    pub fn spawn_push(&mut self, token: &str, user: &str, message: &str,
+                      parameters: Vec<Parameters>) {
+        let token = token.to_string();
+        let user = user.to_string();
+        let message = message.to_string();
+
+        self.sends.push(thread::spawn(move || {
+            let mut client = Client::new(token.as_ref(), user.as_ref());
+            if let Err(errors) = client.send(message.as_ref(), parameters.as_ref()) {
+                warn!("background push failed: {:?}", errors);
             }
-        },
-        Err(code) => Err(vec![format!("curl error {}", code)])
+        }));
+    }
+
+    /// Waits for every send still in flight to finish, such as at shutdown.
+    pub fn wait(self) {
+        for send in self.sends.into_iter() {
+            if send.join().is_err() {
+                warn!("background push thread panicked");
+            }
+        }
+    }
+}
+
+/// Fetches the status of an emergency-priority notification's receipt, using
+/// the specified API token.
+pub fn receipt_status(token: &str, receipt: &str) -> Result<ReceiptStatus, Vec<String>> {
+    receipt_status_with_options(token, receipt, &Options::default())
+}
+
+/// Like `receipt_status`, but with the given request Options.
+pub fn receipt_status_with_options(token: &str, receipt: &str,
+                                   options: &Options) -> Result<ReceiptStatus, Vec<String>> {
+    Client::with_options(token, "", options.clone()).receipt_status(receipt)
+}
+
+/// Fetches the monthly message allowance of the application with the given API
+/// token, without sending a message.
+pub fn app_limits(token: &str) -> Result<RateLimit, Vec<String>> {
+    Client::new(token, "").app_limits()
+}
+
+/// Fetches the status of each of a list of receipts, using the specified API
+/// token, so that an incident's emergency notifications can be reconciled.
+/// Returns each receipt paired with its status, in order, or the errors from
+/// the first lookup that failed.
+pub fn receipt_statuses(token: &str,
+                        receipts: &[String]) -> Result<Vec<(String, ReceiptStatus)>, Vec<String>> {
+    let mut client = Client::new(token, "");
+    let mut statuses = Vec::with_capacity(receipts.len());
+
+    for receipt in receipts.iter() {
+        let status = try!(client.receipt_status(receipt.as_ref()));
+        statuses.push((receipt.clone(), status));
     }
+    Ok(statuses)
+}
+
+/// Like `receipt_statuses`, but only returns the receipts acknowledged after
+/// the given Unix timestamp, such as for a report of who acknowledged alerts
+/// in the last hour at an on-call handoff. Unacknowledged receipts are left
+/// out.
+pub fn receipts_acknowledged_after(token: &str, receipts: &[String],
+                                   after: i64) -> Result<Vec<(String, ReceiptStatus)>, Vec<String>> {
+    let statuses = try!(receipt_statuses(token, receipts));
+
+    Ok(statuses.into_iter()
+               .filter(|&(_, ref status)| status.acknowledged && status.acknowledged_at > after)
+               .collect())
 }
 
 pub fn send_with_url(token: &str, user: &str, message: &str, priority: i8,
@@ -217,3 +2109,488 @@ pub fn send_basic(token: &str, user: &str,
                   message: &str) -> Result<(), Vec<String>> {
     return push(token, user, message, vec![].as_ref());
 }
+
+#[cfg(test)]
+mod tests {
+    use {Client, Options, Overflow, SnippetError, MESSAGE_LIMIT, TRANSPORT_ERROR,
+         is_retryable, retry_after};
+    use super::{api_error, api_response, form_body};
+    use Parameters::*;
+    use transport::{MockTransport, Response};
+    use url::form_urlencoded;
+    use rustc_serialize::json::Json;
+
+    const TOKEN: &'static str = "azGDORePK8gMaC0QOYAMyEEuzJnyUi";
+    const USER: &'static str = "uQiRzpo4DXghDmr9QzzfQu27cmVRsG";
+    const SENT: &'static str =
+        "{\"status\":1,\"request\":\"5042853c-402d-4a18-abcb-168734a801de\"}";
+    const GIST: &'static str =
+        "{\"id\":\"aa5a315d61ae9438b18d\",\
+          \"html_url\":\"https://gist.github.com/aa5a315d61ae9438b18d\",\
+          \"files\":{\"po\":{\"raw_url\":\"https://gist.githubusercontent.com/po\"}}}";
+
+    // A Client whose requests are answered by the returned MockTransport.
+    fn mock_client(options: Options) -> (Client, MockTransport) {
+        let mock = MockTransport::new();
+        let client = Client::with_transport(TOKEN, USER, options, Box::new(mock.clone()));
+        (client, mock)
+    }
+
+    // The decoded fields of a recorded form-encoded request body.
+    fn fields(body: &str) -> Vec<(String, String)> {
+        form_urlencoded::parse(body.as_bytes())
+    }
+
+    fn field(body: &str, name: &str) -> Option<String> {
+        fields(body).into_iter()
+                    .find(|&(ref n, _)| n == name)
+                    .map(|(_, value)| value)
+    }
+
+    // The content of the "po" file in a recorded Gist upload.
+    fn gist_content(body: &str) -> Option<String> {
+        Json::from_str(body).ok()
+            .and_then(|json| json.find_path(&["files", "po", "content"])
+                                 .and_then(|content| content.as_string())
+                                 .map(|content| content.to_string()))
+    }
+
+    fn pairs(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    fn response(code: u32, body: &str) -> Result<Response, String> {
+        Ok(Response {
+            code: code,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec()
+        })
+    }
+
+    #[test]
+    fn push_sends_urlencoded_parameters() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond(200, SENT);
+
+        let outcome = client.send_detailed("Backup finished", &[
+            Priority(1),
+            Title("Backup".to_string()),
+            Sound("siren".to_string()),
+            Device("phone".to_string()),
+            URL("https://example.com".to_string()),
+            URLTitle("Dashboard".to_string()),
+            HTML
+        ]).unwrap();
+
+        assert_eq!(outcome.request_id, "5042853c-402d-4a18-abcb-168734a801de");
+        assert!(!outcome.truncated);
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].url, "https://api.pushover.net/1/messages.json");
+        assert_eq!(fields(requests[0].body.as_ref()), pairs(&[
+            ("token", TOKEN),
+            ("user", USER),
+            ("message", "Backup finished"),
+            ("priority", "1"),
+            ("title", "Backup"),
+            ("sound", "siren"),
+            ("device", "phone"),
+            ("url", "https://example.com"),
+            ("url_title", "Dashboard"),
+            ("html", "1")
+        ]));
+    }
+
+    #[test]
+    fn push_without_parameters_sends_only_the_message() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond(200, SENT);
+
+        client.send("Hello", &[]).unwrap();
+        assert_eq!(fields(mock.requests()[0].body.as_ref()),
+                   pairs(&[("token", TOKEN), ("user", USER), ("message", "Hello")]));
+    }
+
+    #[test]
+    fn pushover_url_is_injectable() {
+        let mut options = Options::default();
+        options.pushover_url = "http://127.0.0.1:8080/1".to_string();
+        let (mut client, mock) = mock_client(options);
+        mock.respond(200, SENT);
+
+        client.send("Hello", &[]).unwrap();
+        assert_eq!(mock.requests()[0].url, "http://127.0.0.1:8080/1/messages.json");
+    }
+
+    #[test]
+    fn api_errors_are_returned() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond(400, "{\"status\":0,\"errors\":[\"user identifier is invalid\"]}");
+
+        assert_eq!(client.send("Hello", &[]),
+                   Err(vec!["user identifier is invalid".to_string()]));
+    }
+
+    #[test]
+    fn server_errors_are_retryable() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond(500, "");
+
+        let errors = client.send("Hello", &[]).unwrap_err();
+        assert_eq!(errors, vec!["API error 500".to_string()]);
+        assert!(is_retryable(&errors));
+    }
+
+    #[test]
+    fn transport_failures_are_retryable() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.fail("connection refused");
+
+        let errors = client.send("Hello", &[]).unwrap_err();
+        assert_eq!(errors, vec![format!("{}connection refused", TRANSPORT_ERROR)]);
+        assert!(is_retryable(&errors));
+    }
+
+    #[test]
+    fn retries_resend_after_a_transport_failure() {
+        let mut options = Options::default();
+        options.retries = 1;
+        options.retry_delay = 0;
+        let (mut client, mock) = mock_client(options);
+        mock.fail("connection reset");
+        mock.respond(200, SENT);
+
+        assert_eq!(client.send("Hello", &[]), Ok(()));
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[test]
+    fn rate_limits_report_retry_after() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond_with_headers(429, &[("retry-after", "30")], "Too Many Requests");
+
+        let errors = client.send("Hello", &[]).unwrap_err();
+        assert_eq!(errors, vec!["rate limited, retry after 30s".to_string()]);
+        assert!(is_retryable(&errors));
+        assert_eq!(retry_after(&errors), Some(30));
+    }
+
+    #[test]
+    fn rate_limits_without_retry_after_are_retryable() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond(429, "Too Many Requests");
+
+        let errors = client.send("Hello", &[]).unwrap_err();
+        assert_eq!(errors, vec!["rate limited".to_string()]);
+        assert!(is_retryable(&errors));
+        assert_eq!(retry_after(&errors), None);
+    }
+
+    #[test]
+    fn rate_limited_sends_are_retried_after_waiting() {
+        let mut options = Options::default();
+        options.retries = 1;
+        options.retry_delay = 60;
+        let (mut client, mock) = mock_client(options);
+        // Retry-After takes the place of the (much longer) retry delay:
+        mock.respond_with_headers(429, &[("retry-after", "0")], "");
+        mock.respond(200, SENT);
+
+        assert_eq!(client.send("Hello", &[]), Ok(()));
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[test]
+    fn github_rate_limits_are_reported() {
+        let mut options = Options::default();
+        options.retries = 1;
+        options.retry_delay = 0;
+        let (mut client, mock) = mock_client(options);
+        mock.respond_with_headers(403, &[("x-ratelimit-remaining", "0"),
+                                         ("x-ratelimit-reset", "1700000000")],
+                                  "{\"message\":\"API rate limit exceeded\"}");
+
+        assert_eq!(client.upload_snippet_retrying("Hello", "po".to_string()),
+                   Err(SnippetError::RateLimited(1700000000)));
+        // GitHub's limits can last an hour, so they aren't retried:
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[test]
+    fn other_github_403s_are_status_errors() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond_with_headers(403, &[("x-ratelimit-remaining", "42")], "{}");
+
+        assert_eq!(client.upload_snippet("Hello", "po".to_string()),
+                   Err(SnippetError::Status(403)));
+    }
+
+    #[test]
+    fn long_messages_are_truncated() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond(200, SENT);
+        let message: String = ::std::iter::repeat('a').take(MESSAGE_LIMIT + 6).collect();
+
+        let outcome = client.send_detailed(message.as_ref(), &[]).unwrap();
+        assert!(outcome.truncated);
+        let sent = field(mock.requests()[0].body.as_ref(), "message").unwrap();
+        assert_eq!(sent, &message[..MESSAGE_LIMIT]);
+    }
+
+    #[test]
+    fn long_messages_are_refused_with_overflow_error() {
+        let mut options = Options::default();
+        options.overflow = Overflow::Error;
+        let (mut client, mock) = mock_client(options);
+        let message: String = ::std::iter::repeat('a').take(MESSAGE_LIMIT + 1).collect();
+
+        assert_eq!(client.send(message.as_ref(), &[]),
+                   Err(vec![format!("message exceeds {} characters", MESSAGE_LIMIT)]));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn api_error_returns_listed_errors() {
+        assert_eq!(api_error("{\"status\":0,\"errors\":[\"message cannot be blank\",\
+                              \"user identifier is invalid\"]}"),
+                   vec!["message cannot be blank".to_string(),
+                        "user identifier is invalid".to_string()]);
+    }
+
+    #[test]
+    fn api_error_without_errors_is_general() {
+        let general = vec!["general API error".to_string()];
+        assert_eq!(api_error("{\"status\":0,\"errors\":[]}"), general);
+        assert_eq!(api_error("{\"status\":0}"), general);
+    }
+
+    #[test]
+    fn api_error_for_non_json_is_general() {
+        assert_eq!(api_error("<html>Bad Gateway</html>"),
+                   vec!["general API error".to_string()]);
+    }
+
+    #[test]
+    fn api_response_accepts_success() {
+        assert_eq!(api_response(response(200, SENT)), Ok(SENT.to_string()));
+    }
+
+    #[test]
+    fn api_response_rejects_200_without_success_status() {
+        assert_eq!(api_response(response(200, "{\"status\":0,\
+                                                \"errors\":[\"token is invalid\"]}")),
+                   Err(vec!["token is invalid".to_string()]));
+        assert_eq!(api_response(response(200, "{\"status\":0}")),
+                   Err(vec!["general API error".to_string()]));
+    }
+
+    #[test]
+    fn api_response_tolerates_bodies_that_arent_utf8() {
+        let page = Ok(Response {
+            code: 502,
+            headers: Vec::new(),
+            body: vec![0x3c, 0xff, 0xfe, 0x3e]
+        });
+        assert_eq!(api_response(page), Err(vec!["API error 502".to_string()]));
+        let page = Ok(Response {
+            code: 400,
+            headers: Vec::new(),
+            body: vec![0xff]
+        });
+        assert_eq!(api_response(page), Err(vec!["malformed API response".to_string()]));
+    }
+
+    #[test]
+    fn title_of_250_characters_is_sent() {
+        let (mut client, mock) = mock_client(Options::default());
+        mock.respond(200, SENT);
+        // Multibyte, so that the limit is checked in characters, not bytes:
+        let title: String = ::std::iter::repeat('é').take(250).collect();
+
+        client.send("Hello", &[Title(title.clone())]).unwrap();
+        assert_eq!(field(mock.requests()[0].body.as_ref(), "title"), Some(title));
+    }
+
+    #[test]
+    fn title_of_251_characters_is_refused() {
+        let (mut client, mock) = mock_client(Options::default());
+        let title: String = ::std::iter::repeat('é').take(251).collect();
+
+        assert_eq!(client.send("Hello", &[Title(title)]),
+                   Err(vec!["title exceeds 250 characters".to_string()]));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn empty_messages_are_refused() {
+        let (mut client, mock) = mock_client(Options::default());
+
+        for message in ["", " ", "\n\t  \n"].iter() {
+            assert_eq!(client.send(message, &[]),
+                       Err(vec!["message is empty".to_string()]));
+        }
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn allow_empty_sends_a_space() {
+        let mut options = Options::default();
+        options.allow_empty = true;
+        let (mut client, mock) = mock_client(options);
+        mock.respond(200, SENT);
+        mock.respond(200, SENT);
+
+        client.send("", &[]).unwrap();
+        client.send(" \n", &[]).unwrap();
+        let requests = mock.requests();
+        assert_eq!(field(requests[0].body.as_ref(), "message"), Some(" ".to_string()));
+        assert_eq!(field(requests[1].body.as_ref(), "message"), Some(" ".to_string()));
+    }
+
+    #[test]
+    fn truncation_does_not_reach_the_gist() {
+        let mut options = Options::default();
+        options.max_message_len = 10;
+        let (mut client, mock) = mock_client(options);
+        mock.respond(201, GIST);
+        mock.respond(200, SENT);
+        let message = "0123456789abcdefghij";
+
+        let outcome = client.send_detailed(message, &[Gist]).unwrap();
+        assert!(outcome.truncated);
+        assert_eq!(outcome.gist_url,
+                   Some("https://gist.github.com/aa5a315d61ae9438b18d".to_string()));
+        let requests = mock.requests();
+        assert_eq!(requests[0].url, "https://api.github.com/gists");
+        assert_eq!(gist_content(requests[0].body.as_ref()), Some(message.to_string()));
+        let body: &str = requests[1].body.as_ref();
+        assert_eq!(field(body, "message"), Some("0123456789".to_string()));
+        assert_eq!(field(body, "url"),
+                   Some("https://gist.github.com/aa5a315d61ae9438b18d".to_string()));
+    }
+
+    #[test]
+    fn splitting_does_not_reach_the_gist() {
+        let mut options = Options::default();
+        options.max_message_len = 10;
+        let (mut client, mock) = mock_client(options);
+        mock.respond(201, GIST);
+        mock.respond(200, SENT);
+        mock.respond(200, SENT);
+        let message = "0123456789abcdefghij";
+
+        assert_eq!(client.send_split(message, &[Gist]), vec![Ok(()), Ok(())]);
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(gist_content(requests[0].body.as_ref()), Some(message.to_string()));
+        for (request, part) in requests[1..].iter().zip(["0123456789", "abcdefghij"].iter()) {
+            let body: &str = request.body.as_ref();
+            assert_eq!(field(body, "message"), Some(part.to_string()));
+            assert_eq!(field(body, "url"),
+                       Some("https://gist.github.com/aa5a315d61ae9438b18d".to_string()));
+        }
+    }
+
+    #[test]
+    fn split_titles_are_shortened_to_fit_the_part_number() {
+        let mut options = Options::default();
+        options.max_message_len = 10;
+        let (mut client, mock) = mock_client(options);
+        mock.respond(200, SENT);
+        mock.respond(200, SENT);
+        let title: String = ::std::iter::repeat('é').take(250).collect();
+
+        assert_eq!(client.send_split("0123456789abcdefghij", &[Title(title.clone())]),
+                   vec![Ok(()), Ok(())]);
+        let requests = mock.requests();
+        for (i, request) in requests.iter().enumerate() {
+            let sent = field(request.body.as_ref(), "title").unwrap();
+            assert_eq!(sent.chars().count(), 250);
+            assert_eq!(sent, format!("({}/2) {}", i + 1, &title[..244 * 2]));
+        }
+    }
+
+    #[test]
+    fn split_titles_over_the_limit_are_refused() {
+        let mut options = Options::default();
+        options.max_message_len = 10;
+        let (mut client, mock) = mock_client(options);
+        let title: String = ::std::iter::repeat('é').take(251).collect();
+
+        assert_eq!(client.send_split("0123456789abcdefghij", &[Title(title)]),
+                   vec![Err(vec!["title exceeds 250 characters".to_string()])]);
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn retried_splits_neither_redeliver_nor_reupload() {
+        let mut options = Options::default();
+        options.max_message_len = 10;
+        options.overflow = Overflow::Split;
+        options.retries = 1;
+        options.retry_delay = 0;
+        let (mut client, mock) = mock_client(options);
+        mock.respond(201, GIST);
+        mock.respond(200, SENT);
+        mock.fail("connection reset");
+        mock.respond(200, SENT);
+
+        assert_eq!(client.send("0123456789abcdefghij", &[Gist]), Ok(()));
+        let requests = mock.requests();
+        let messages: Vec<Option<String>> = requests[1..].iter()
+            .map(|request| field(request.body.as_ref(), "message"))
+            .collect();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(messages, vec![Some("0123456789".to_string()),
+                                  Some("abcdefghij".to_string()),
+                                  Some("abcdefghij".to_string())]);
+    }
+
+    #[test]
+    fn retried_sends_dont_reupload() {
+        let mut options = Options::default();
+        options.retries = 1;
+        options.retry_delay = 0;
+        let (mut client, mock) = mock_client(options);
+        mock.respond(201, GIST);
+        mock.fail("connection reset");
+        mock.respond(200, SENT);
+
+        let outcome = client.send_detailed("Hello", &[Gist]).unwrap();
+        assert_eq!(outcome.gist_url,
+                   Some("https://gist.github.com/aa5a315d61ae9438b18d".to_string()));
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(gist_content(requests[0].body.as_ref()), Some("Hello".to_string()));
+        assert!(gist_content(requests[2].body.as_ref()).is_none());
+    }
+
+    #[test]
+    fn form_body_percent_encodes_reserved_characters() {
+        assert_eq!(form_body(pairs(&[("message", "a&b=c%d\n\te f+g é")])),
+                   "message=a%26b%3Dc%25d%0A%09e+f%2Bg+%C3%A9");
+    }
+
+    #[test]
+    fn messages_reach_pushover_intact() {
+        let long_line: String = ::std::iter::repeat("x=1&y=2 ").take(120).collect();
+        let messages = [
+            "line one\nline two\r\nline three".to_string(),
+            "tab\tseparated\tcolumns".to_string(),
+            "a & b = c, 100% done; 1+1".to_string(),
+            "unicode: héllo wörld, 日本語, 🎉".to_string(),
+            "%0A%26 stays literal".to_string(),
+            long_line
+        ];
+
+        for message in messages.iter() {
+            let (mut client, mock) = mock_client(Options::default());
+            mock.respond(200, SENT);
+
+            client.send(message.as_ref(), &[]).unwrap();
+            let requests = mock.requests();
+            assert_eq!(field(requests[0].body.as_ref(), "message").as_ref(), Some(message));
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Conversion of simple Markdown into the HTML subset Pushover renders.
+
+/// Converts `**bold**`, `*italic*`, and `[text](url)` Markdown into Pushover's
+/// `<b>`, `<i>`, and `<a href>` tags, escaping any other `&`, `<`, and `>` so
+/// they display as written. Other Markdown is left as plain text. Send the
+/// result with `Parameters::HTML`.
+pub fn markdown_to_pushover_html(input: &str) -> String {
+    let mut html = String::new();
+    let mut rest = input;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("**") {
+            if let Some(end) = rest[2..].find("**") {
+                if end > 0 {
+                    html.push_str("<b>");
+                    html.push_str(markdown_to_pushover_html(&rest[2..2 + end]).as_ref());
+                    html.push_str("</b>");
+                    rest = &rest[2 + end + 2..];
+                    continue;
+                }
+            }
+        }
+        else if c == '*' {
+            if let Some(end) = rest[1..].find('*') {
+                if end > 0 {
+                    html.push_str("<i>");
+                    html.push_str(markdown_to_pushover_html(&rest[1..1 + end]).as_ref());
+                    html.push_str("</i>");
+                    rest = &rest[1 + end + 1..];
+                    continue;
+                }
+            }
+        }
+        else if c == '[' {
+            // The link text ends at the first `]`, which must be followed
+            // directly by the `(` of the URL:
+            let middle = rest.find(']').and_then(|close| {
+                if rest[close..].starts_with("](") { Some(close) } else { None }
+            });
+            if let Some(middle) = middle {
+                if let Some(end) = rest[middle + 2..].find(')') {
+                    let text = &rest[1..middle];
+                    let url = &rest[middle + 2..middle + 2 + end];
+                    html.push_str(format!("<a href=\"{}\">{}</a>",
+                                          escape(url).replace("\"", "&quot;"),
+                                          markdown_to_pushover_html(text)).as_ref());
+                    rest = &rest[middle + 2 + end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        html.push_str(escape(&rest[..c.len_utf8()]).as_ref());
+        rest = &rest[c.len_utf8()..];
+    }
+    html
+}
+
+// Escape the characters HTML gives a meaning to.
+fn escape(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::markdown_to_pushover_html;
+
+    #[test]
+    fn bold() {
+        assert_eq!(markdown_to_pushover_html("a **b** c"), "a <b>b</b> c");
+    }
+
+    #[test]
+    fn italic() {
+        assert_eq!(markdown_to_pushover_html("a *b* c"), "a <i>b</i> c");
+    }
+
+    #[test]
+    fn nested_emphasis() {
+        assert_eq!(markdown_to_pushover_html("**a *b* c**"), "<b>a <i>b</i> c</b>");
+    }
+
+    #[test]
+    fn link() {
+        assert_eq!(markdown_to_pushover_html("see [docs](https://example.com/a?b=1&c=2)"),
+                   "see <a href=\"https://example.com/a?b=1&amp;c=2\">docs</a>");
+    }
+
+    #[test]
+    fn link_after_brackets() {
+        assert_eq!(markdown_to_pushover_html("see [1] and [docs](http://x)"),
+                   "see [1] and <a href=\"http://x\">docs</a>");
+    }
+
+    #[test]
+    fn link_url_quotes_escaped() {
+        assert_eq!(markdown_to_pushover_html("[x](http://x/\"y)"),
+                   "<a href=\"http://x/&quot;y\">x</a>");
+    }
+
+    #[test]
+    fn unclosed_markup_left_as_text() {
+        assert_eq!(markdown_to_pushover_html("**a and [b] and [c](d"),
+                   "**a and [b] and [c](d");
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(markdown_to_pushover_html("a < b && c > d"),
+                   "a &lt; b &amp;&amp; c &gt; d");
+    }
+
+    #[test]
+    fn escapes_html_inside_markup() {
+        assert_eq!(markdown_to_pushover_html("**<script>**"),
+                   "<b>&lt;script&gt;</b>");
+    }
+
+    #[test]
+    fn multibyte_text() {
+        assert_eq!(markdown_to_pushover_html("*héllo* 🎉"), "<i>héllo</i> 🎉");
+    }
+}
@@ -0,0 +1,55 @@
+//! Message templates with `{placeholder}` substitution.
+
+use time;
+
+/// Returns the local hostname, or None if it can't be determined.
+pub fn hostname() -> Option<String> {
+    match ::hostname::get() {
+        Ok(name) => {
+            let name = name.to_string_lossy().trim().to_string();
+            if name != "" {
+                Some(name)
+            }
+            else {
+                None
+            }
+        },
+        Err(_) => None
+    }
+}
+
+/// Prefix a title with the local hostname, as "host: title", or return just
+/// the hostname if there's no title. If the hostname can't be determined, the
+/// title is returned unchanged.
+pub fn with_hostname(title: Option<&str>) -> Option<String> {
+    match (hostname(), title) {
+        (Some(host), Some(t)) => Some(format!("{}: {}", host, t)),
+        (Some(host), None) => Some(host),
+        (None, t) => t.map(|t| t.to_string())
+    }
+}
+
+/// Expand a template string, replacing each `{name}` placeholder with the
+/// matching value from `values`. The placeholders `{hostname}` (the local
+/// hostname) and `{time}` (the current local time) are always available
+/// unless overridden by `values`.
+pub fn expand(template: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = template.to_string();
+
+    for &(name, value) in values.iter() {
+        let placeholder = format!("{{{}}}", name);
+        let placeholder_ref: &str = placeholder.as_ref();
+        expanded = expanded.replace(placeholder_ref, value);
+    }
+    if expanded.contains("{hostname}") {
+        let host = hostname().unwrap_or("unknown host".to_string());
+        let host_ref: &str = host.as_ref();
+        expanded = expanded.replace("{hostname}", host_ref);
+    }
+    if expanded.contains("{time}") {
+        let now = time::strftime("%Y-%m-%d %H:%M:%S", &time::now()).unwrap();
+        let now_ref: &str = now.as_ref();
+        expanded = expanded.replace("{time}", now_ref);
+    }
+    expanded
+}
@@ -0,0 +1,223 @@
+//! The HTTP transport that a `Client` makes its requests through.
+//!
+//! Clients use `CurlTransport` unless given another with
+//! `Client::with_transport`, such as a `MockTransport` for testing code that
+//! sends notifications without reaching the network.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use curl::http;
+
+/// An HTTP response
+#[derive(PartialEq, Clone, Debug)]
+pub struct Response {
+    /// The HTTP status code
+    pub code: u32,
+    /// The response headers, with lowercase names
+    pub headers: Vec<(String, String)>,
+    /// The response body
+    pub body: Vec<u8>
+}
+
+impl Response {
+    /// Returns the value of the first header with the given lowercase name,
+    /// if any.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+                    .find(|&&(ref n, _)| n == name)
+                    .map(|&(_, ref value)| value.as_ref())
+    }
+}
+
+/// A way of making HTTP requests. Each method sends a request with the given
+/// headers and returns the response, or a description of why no response
+/// was received, such as a connection that couldn't be made.
+pub trait Transport {
+    fn get(&mut self, url: &str,
+           headers: &[(String, String)]) -> Result<Response, String>;
+    fn post(&mut self, url: &str, body: &str,
+            headers: &[(String, String)]) -> Result<Response, String>;
+    fn delete(&mut self, url: &str,
+              headers: &[(String, String)]) -> Result<Response, String>;
+}
+
+/// Makes requests with curl, keeping one handle, and so its open
+/// connections, between requests
+pub struct CurlTransport {
+    handle: http::Handle
+}
+
+impl CurlTransport {
+    /// Creates a CurlTransport, which skips TLS certificate verification if
+    /// `insecure` is set (see `Options::insecure`).
+    pub fn new(insecure: bool) -> CurlTransport {
+        let handle = if insecure {
+            http::handle().ssl_verifypeer(false)
+        }
+        else {
+            http::handle()
+        };
+
+        CurlTransport { handle: handle }
+    }
+}
+
+// Add headers to a curl request.
+fn with_headers<'a, 'b>(mut request: http::Request<'a, 'b>,
+                        headers: &[(String, String)]) -> http::Request<'a, 'b> {
+    for &(ref name, ref value) in headers.iter() {
+        let name_ref: &str = name.as_ref();
+        let value_ref: &str = value.as_ref();
+        request = request.header(name_ref, value_ref);
+    }
+    request
+}
+
+fn curl_response(result: Result<http::Response, ::curl::ErrCode>) -> Result<Response, String> {
+    match result {
+        Ok(res) => {
+            let mut headers = Vec::new();
+            for (name, values) in res.get_headers().iter() {
+                for value in values.iter() {
+                    headers.push((name.to_lowercase(), value.clone()));
+                }
+            }
+            Ok(Response {
+                code: res.get_code(),
+                headers: headers,
+                body: res.get_body().to_vec()
+            })
+        },
+        Err(code) => {
+            let number = code.0 as i32;
+            match curl_error_message(number) {
+                Some(message) => Err(format!("{} (curl error {})", message, number)),
+                None => Err(format!("curl error {}", number))
+            }
+        }
+    }
+}
+
+// Describe the curl errors users commonly run into, so that they know what
+// to fix without looking up the code.
+fn curl_error_message(code: i32) -> Option<&'static str> {
+    match code {
+        5 => Some("couldn't resolve the proxy's host name"),
+        6 => Some("couldn't resolve the host name; check your network connection and DNS"),
+        7 => Some("couldn't connect to the server; the connection was refused or the \
+                   host is unreachable"),
+        28 => Some("the request timed out"),
+        35 => Some("the TLS handshake failed"),
+        51 | 60 => Some("the server's TLS certificate couldn't be verified; a proxy may be \
+                         intercepting the connection"),
+        52 => Some("the server closed the connection without replying"),
+        55 | 56 => Some("the connection was lost while sending or receiving data"),
+        77 => Some("couldn't read the CA certificates used to verify the server"),
+        _ => None
+    }
+}
+
+impl Transport for CurlTransport {
+    fn get(&mut self, url: &str,
+           headers: &[(String, String)]) -> Result<Response, String> {
+        curl_response(with_headers(self.handle.get(url), headers).exec())
+    }
+
+    fn post(&mut self, url: &str, body: &str,
+            headers: &[(String, String)]) -> Result<Response, String> {
+        curl_response(with_headers(self.handle.post(url, body), headers).exec())
+    }
+
+    fn delete(&mut self, url: &str,
+              headers: &[(String, String)]) -> Result<Response, String> {
+        curl_response(with_headers(self.handle.delete(url), headers).exec())
+    }
+}
+
+/// A request recorded by a `MockTransport`
+#[derive(PartialEq, Clone, Debug)]
+pub struct MockRequest {
+    /// "GET", "POST", or "DELETE"
+    pub method: String,
+    pub url: String,
+    /// The request body ("" for GET and DELETE)
+    pub body: String,
+    pub headers: Vec<(String, String)>
+}
+
+/// A Transport that makes no requests, recording each one and answering it
+/// with the next queued response. Clones share their requests and responses,
+/// so keep a clone to inspect the requests made by a Client given another.
+#[derive(Clone)]
+pub struct MockTransport {
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    responses: Arc<Mutex<VecDeque<Result<Response, String>>>>
+}
+
+impl MockTransport {
+    /// Creates a MockTransport with no requests or queued responses.
+    pub fn new() -> MockTransport {
+        MockTransport {
+            requests: Arc::new(Mutex::new(Vec::new())),
+            responses: Arc::new(Mutex::new(VecDeque::new()))
+        }
+    }
+
+    /// Queues a response with the given status code and body.
+    pub fn respond(&self, code: u32, body: &str) {
+        self.respond_with_headers(code, &[], body);
+    }
+
+    /// Like `respond`, but with the given headers, whose names should be
+    /// lowercase (see `Response::header`).
+    pub fn respond_with_headers(&self, code: u32, headers: &[(&str, &str)], body: &str) {
+        self.responses.lock().unwrap().push_back(Ok(Response {
+            code: code,
+            headers: headers.iter()
+                            .map(|&(n, v)| (n.to_string(), v.to_string()))
+                            .collect(),
+            body: body.as_bytes().to_vec()
+        }));
+    }
+
+    /// Queues a transport failure, as if no response was received.
+    pub fn fail(&self, error: &str) {
+        self.responses.lock().unwrap().push_back(Err(error.to_string()));
+    }
+
+    /// Returns every request made so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn record(&mut self, method: &str, url: &str, body: &str,
+              headers: &[(String, String)]) -> Result<Response, String> {
+        self.requests.lock().unwrap().push(MockRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            body: body.to_string(),
+            headers: headers.to_vec()
+        });
+        match self.responses.lock().unwrap().pop_front() {
+            Some(response) => response,
+            None => Err(format!("no mock response queued for {} {}", method, url))
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn get(&mut self, url: &str,
+           headers: &[(String, String)]) -> Result<Response, String> {
+        self.record("GET", url, "", headers)
+    }
+
+    fn post(&mut self, url: &str, body: &str,
+            headers: &[(String, String)]) -> Result<Response, String> {
+        self.record("POST", url, body, headers)
+    }
+
+    fn delete(&mut self, url: &str,
+              headers: &[(String, String)]) -> Result<Response, String> {
+        self.record("DELETE", url, "", headers)
+    }
+}
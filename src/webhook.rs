@@ -0,0 +1,71 @@
+//! Forwarding JSON webhook payloads as notifications.
+
+use rustc_serialize::json::Json;
+use {push, Parameters};
+
+/// Where to find a notification's fields in a webhook payload, as dotted
+/// paths through its JSON objects, such as "alert.summary"
+#[derive(PartialEq, Clone, Debug)]
+pub struct FieldMap {
+    /// The path to the message body; a payload without it is an error
+    pub message: String,
+    /// The path to the title, if any; it's left out if the payload lacks it
+    pub title: Option<String>,
+    /// The path to the priority, if any, which must be an integer from -2 to
+    /// 2 when present; it's left out if the payload lacks it
+    pub priority: Option<String>
+}
+
+// Look up a dotted path in a payload, rendering strings as they are and any
+// other JSON value as JSON.
+fn field(payload: &Json, path: &str) -> Option<String> {
+    let keys: Vec<&str> = path.split('.').collect();
+    payload.find_path(&keys).map(|value| {
+        match *value {
+            Json::String(ref s) => s.clone(),
+            ref other => other.to_string()
+        }
+    })
+}
+
+/// Builds the message body and parameters of a notification from a JSON
+/// webhook payload, taking each field from the path the FieldMap gives it.
+/// Returns an error if the payload isn't JSON, lacks the message field, or
+/// has a priority that isn't an integer from -2 to 2.
+pub fn notification(payload: &str,
+                    map: &FieldMap) -> Result<(String, Vec<Parameters>), Vec<String>> {
+    let json = match Json::from_str(payload) {
+        Ok(json) => json,
+        Err(e) => return Err(vec![format!("webhook payload isn't JSON: {}", e)])
+    };
+    let message = match field(&json, map.message.as_ref()) {
+        Some(message) => message,
+        None => return Err(vec![format!("webhook payload has no {}", map.message)])
+    };
+    let mut parameters = Vec::new();
+
+    if let Some(ref path) = map.title {
+        if let Some(title) = field(&json, path.as_ref()) {
+            parameters.push(Parameters::Title(title));
+        }
+    }
+    if let Some(ref path) = map.priority {
+        if let Some(priority) = field(&json, path.as_ref()) {
+            match priority.trim().parse::<i8>() {
+                Ok(p) if p >= -2 && p <= 2 => parameters.push(Parameters::Priority(p)),
+                _ => return Err(vec![format!("webhook priority {} isn't from -2 to 2",
+                                             priority)])
+            }
+        }
+    }
+    Ok((message, parameters))
+}
+
+/// Pushes a notification built from a JSON webhook payload (see
+/// `notification`) with the specified API token and user key, for bridging a
+/// service's webhooks to Pushover.
+pub fn forward(token: &str, user: &str, payload: &str,
+               map: &FieldMap) -> Result<(), Vec<String>> {
+    let (message, parameters) = try!(notification(payload, map));
+    push(token, user, message.as_ref(), parameters.as_ref())
+}